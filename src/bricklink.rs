@@ -0,0 +1,155 @@
+//! BrickLink wanted list XML
+//! (<https://www.bricklink.com/help.asp?helpID=207>) for a set's parts.
+//!
+//! Items are identified by their Rebrickable part number, which for many
+//! parts isn't the one BrickLink uses.
+
+use crate::catalog::{Color, InventoryPart};
+use quick_xml::escape::escape;
+use std::fmt::Write;
+
+/// BrickLink color ids by the Rebrickable color name. Rebrickable's dumps
+/// don't include BrickLink ids, but mostly use BrickLink's names.
+const COLORS: &[(&str, u32)] = &[
+    ("White", 1),
+    ("Tan", 2),
+    ("Yellow", 3),
+    ("Orange", 4),
+    ("Red", 5),
+    ("Green", 6),
+    ("Blue", 7),
+    ("Brown", 8),
+    ("Light Gray", 9),
+    ("Dark Gray", 10),
+    ("Black", 11),
+    ("Trans-Clear", 12),
+    ("Trans-Black", 13),
+    ("Trans-Dark Blue", 14),
+    ("Trans-Light Blue", 15),
+    ("Trans-Neon Green", 16),
+    ("Trans-Red", 17),
+    ("Trans-Neon Orange", 18),
+    ("Trans-Yellow", 19),
+    ("Trans-Green", 20),
+    ("Chrome Gold", 21),
+    ("Chrome Silver", 22),
+    ("Pink", 23),
+    ("Purple", 24),
+    ("Salmon", 25),
+    ("Nougat", 28),
+    ("Medium Orange", 31),
+    ("Light Yellow", 33),
+    ("Lime", 34),
+    ("Bright Green", 36),
+    ("Dark Turquoise", 39),
+    ("Medium Blue", 42),
+    ("Dark Pink", 47),
+    ("Sand Green", 48),
+    ("Very Light Gray", 49),
+    ("Trans-Dark Pink", 50),
+    ("Trans-Purple", 51),
+    ("Sand Blue", 55),
+    ("Dark Red", 59),
+    ("Dark Blue", 63),
+    ("Metallic Gold", 65),
+    ("Pearl Light Gray", 66),
+    ("Metallic Silver", 67),
+    ("Dark Orange", 68),
+    ("Dark Tan", 69),
+    ("Magenta", 71),
+    ("Trans-Medium Blue", 74),
+    ("Pearl Dark Gray", 77),
+    ("Dark Green", 80),
+    ("Flat Dark Gold", 81),
+    ("Pearl White", 83),
+    ("Dark Bluish Gray", 85),
+    ("Light Bluish Gray", 86),
+    ("Reddish Brown", 88),
+    ("Dark Purple", 89),
+    ("Light Nougat", 90),
+    ("Flat Silver", 95),
+    ("Trans-Orange", 98),
+    ("Bright Light Yellow", 103),
+    ("Bright Pink", 104),
+    ("Bright Light Blue", 105),
+    ("Trans-Pink", 107),
+    ("Trans-Bright Green", 108),
+    ("Bright Light Orange", 110),
+    ("Pearl Gold", 115),
+    ("Dark Brown", 120),
+    ("Medium Nougat", 150),
+    ("Light Aqua", 152),
+    ("Dark Azure", 153),
+    ("Lavender", 154),
+    ("Olive Green", 155),
+    ("Medium Azure", 156),
+    ("Medium Lavender", 157),
+    ("Yellowish Green", 158),
+    ("Glow In Dark White", 159),
+    ("Coral", 220),
+];
+
+/// The BrickLink id of `color`, if it's known
+pub fn color_id(color: &Color) -> Option<u32> {
+    COLORS
+        .iter()
+        .find(|(name, _)| *name == color.name)
+        .map(|(_, id)| *id)
+}
+
+/// A wanted list of the parts in `inventory`, leaving out spares. Parts in
+/// colors without a known BrickLink id are left out too, since an item
+/// without a color would match any color.
+pub fn wanted_list(inventory: &[InventoryPart]) -> String {
+    let mut xml = String::from("<INVENTORY>\n");
+    for line in inventory.iter().filter(|l| !l.spare) {
+        let Some(color) = color_id(&line.color) else {
+            continue;
+        };
+        xml.push_str("<ITEM><ITEMTYPE>P</ITEMTYPE>");
+        write!(xml, "<ITEMID>{}</ITEMID>", escape(&line.part.number)).unwrap();
+        write!(xml, "<COLOR>{}</COLOR>", color).unwrap();
+        writeln!(xml, "<MINQTY>{}</MINQTY></ITEM>", line.quantity).unwrap();
+    }
+    xml.push_str("</INVENTORY>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::Part;
+    use std::sync::Arc;
+
+    fn line(number: &str, color: &str, quantity: u32, spare: bool) -> InventoryPart {
+        InventoryPart {
+            part: Arc::new(Part {
+                number: number.to_string(),
+                name: String::new(),
+            }),
+            color: Arc::new(Color {
+                name: color.to_string(),
+                rgb: String::new(),
+            }),
+            quantity,
+            spare,
+        }
+    }
+
+    #[test]
+    fn test_wanted_list() {
+        let inventory = [
+            line("3001", "Light Bluish Gray", 12, false),
+            line("973pb1<&>", "Light Nougat", 1, false),
+            line("3004", "Black", 1, true),
+            line("9999", "Glitter Trans & Stuff", 2, false),
+        ];
+        assert_eq!(
+            wanted_list(&inventory),
+            "<INVENTORY>\n\
+             <ITEM><ITEMTYPE>P</ITEMTYPE><ITEMID>3001</ITEMID><COLOR>86</COLOR><MINQTY>12</MINQTY></ITEM>\n\
+             <ITEM><ITEMTYPE>P</ITEMTYPE><ITEMID>973pb1&lt;&amp;&gt;</ITEMID><COLOR>90</COLOR><MINQTY>1</MINQTY></ITEM>\n\
+             </INVENTORY>\n"
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use crate::metadata::OVERRIDES_FILE_NAME;
+use crate::paths;
+use crate::scan::Library;
+use crate::{File, file_stats};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Bump whenever the serialized shape of `File` changes, so stale caches are
+/// discarded instead of failing to deserialize.
+const CACHE_VERSION: u32 = 10;
+
+#[derive(Serialize)]
+struct CacheRef<'a> {
+    version: u32,
+    files: &'a [File],
+    overrides: Vec<OverridesModified>,
+}
+
+#[derive(Deserialize)]
+struct CacheVersion {
+    version: u32,
+}
+
+#[derive(Deserialize)]
+struct CacheOwned {
+    files: Vec<File>,
+    overrides: Vec<OverridesModified>,
+}
+
+/// When a library's overrides file was last modified. Kept in a list rather
+/// than a map, since map keys have to be strings and paths may not be UTF-8.
+#[derive(Serialize, Deserialize)]
+struct OverridesModified {
+    #[serde(with = "paths")]
+    path: PathBuf,
+    modified: SystemTime,
+}
+
+/// Previously scanned files, keyed by absolute path.
+#[derive(Default)]
+pub struct IndexCache {
+    files: HashMap<PathBuf, File>,
+    /// Modification times of the overrides files the cached files were
+    /// scanned with, by path
+    overrides: HashMap<PathBuf, SystemTime>,
+}
+
+impl IndexCache {
+    /// Default cache location under the user's state directory.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::state_dir()
+            .or_else(dirs::cache_dir)
+            .map(|d| d.join("lview").join("index.json"))
+    }
+
+    /// Load the cache, treating a missing or unreadable cache as empty.
+    pub fn load(path: &Path) -> Self {
+        match Self::try_load(path) {
+            Ok(cache) => cache,
+            Err(e) => {
+                if path.exists() {
+                    eprintln!("ignoring cache {}: {:#}", path.display(), e);
+                }
+                Self::default()
+            }
+        }
+    }
+
+    fn try_load(path: &Path) -> Result<Self> {
+        let data = fs::read(path)?;
+        let header: CacheVersion = serde_json::from_slice(&data)?;
+        if header.version != CACHE_VERSION {
+            return Ok(Self::default());
+        }
+        let cache: CacheOwned = serde_json::from_slice(&data)?;
+        let files = cache
+            .files
+            .into_iter()
+            .map(|f| (f.path.clone(), f))
+            .collect();
+        Ok(Self {
+            files,
+            overrides: cache
+                .overrides
+                .into_iter()
+                .map(|o| (o.path, o.modified))
+                .collect(),
+        })
+    }
+
+    /// Take the cached entry for `path` if the file on disk hasn't changed
+    /// size or modification time since it was scanned, and the library's
+    /// overrides haven't been added, changed or removed since.
+    pub fn take(&mut self, path: &Path, library: &Library) -> Option<File> {
+        let overrides_path = library.overrides_path();
+        if library.overrides_modified() != self.overrides.get(&overrides_path).copied() {
+            return None;
+        }
+        let (size, modified) = file_stats(path).ok()?;
+        let mut file = self
+            .files
+            .remove(path)
+            .filter(|f| f.size == size && f.modified == modified)?;
+        file.relative_path = path.strip_prefix(&library.dir).ok()?.to_path_buf();
+        file.root = library.label.clone();
+        // joined afresh, since the catalog may have been updated
+        file.catalog = library.catalog_set(&file.set_number());
+        Some(file)
+    }
+
+    /// Write `files` to the cache at `path`, replacing it atomically.
+    pub fn save(path: &Path, files: &[File]) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        // the overrides file of each library, found from its files' paths
+        let library_dirs: HashSet<&Path> = files
+            .iter()
+            .filter_map(|f| f.path.ancestors().nth(f.relative_path.components().count()))
+            .collect();
+        let overrides = library_dirs
+            .into_iter()
+            .filter_map(|dir| {
+                let path = dir.join(OVERRIDES_FILE_NAME);
+                let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+                Some(OverridesModified { path, modified })
+            })
+            .collect();
+        let data = serde_json::to_vec(&CacheRef {
+            version: CACHE_VERSION,
+            files,
+            overrides,
+        })?;
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, data)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,272 @@
+//! Set details from the Rebrickable catalog dumps
+//! (<https://rebrickable.com/downloads/>), joined to files by set number.
+
+use crate::set_number::SetNumber;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// What the catalog knows about a set
+#[derive(Debug)]
+pub struct CatalogSet {
+    /// Official name of the set
+    pub name: String,
+    pub parts: u32,
+    /// Minifigures in the set, if the inventories were imported
+    pub minifigs: Option<u32>,
+    /// The set's theme and its parents, outermost first
+    pub themes: Vec<String>,
+    /// Parts in the set, if the part inventories were imported
+    pub inventory: Vec<InventoryPart>,
+}
+
+/// A line of a set's parts inventory
+#[derive(Debug)]
+pub struct InventoryPart {
+    pub part: Arc<Part>,
+    pub color: Arc<Color>,
+    pub quantity: u32,
+    pub spare: bool,
+}
+
+#[derive(Debug)]
+pub struct Part {
+    pub number: String,
+    pub name: String,
+}
+
+#[derive(Debug)]
+pub struct Color {
+    pub name: String,
+    /// Hex RGB, without a leading `#`
+    pub rgb: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Catalog {
+    sets: HashMap<SetNumber, Arc<CatalogSet>>,
+}
+
+#[derive(Deserialize)]
+struct SetRecord {
+    set_num: String,
+    name: String,
+    theme_id: u32,
+    num_parts: u32,
+}
+
+#[derive(Deserialize)]
+struct ThemeRecord {
+    id: u32,
+    name: String,
+    parent_id: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct InventoryRecord {
+    id: u32,
+    version: u32,
+    set_num: String,
+}
+
+#[derive(Deserialize)]
+struct InventoryMinifigRecord {
+    inventory_id: u32,
+    quantity: u32,
+}
+
+#[derive(Deserialize)]
+struct InventoryPartRecord {
+    inventory_id: u32,
+    part_num: String,
+    color_id: i32,
+    quantity: u32,
+    is_spare: String,
+}
+
+#[derive(Deserialize)]
+struct PartRecord {
+    part_num: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ColorRecord {
+    id: i32,
+    name: String,
+    rgb: String,
+}
+
+impl Catalog {
+    /// Read `sets.csv` and `themes.csv` from `dir`. If they are there, also
+    /// read `inventories.csv` with `inventory_minifigs.csv` for minifigure
+    /// counts, and with `inventory_parts.csv`, `parts.csv` and `colors.csv`
+    /// for parts lists.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let themes: HashMap<u32, ThemeRecord> = read_csv(&dir.join("themes.csv"))?
+            .into_iter()
+            .map(|t: ThemeRecord| (t.id, t))
+            .collect();
+        let has_minifigs = dir.join("inventory_minifigs.csv").exists();
+        let has_parts = dir.join("inventory_parts.csv").exists();
+        let inventories = if has_minifigs || has_parts {
+            read_inventories(dir)?
+        } else {
+            HashMap::new()
+        };
+        let minifigs = if has_minifigs {
+            Some(read_minifigs(dir)?)
+        } else {
+            None
+        };
+        let mut parts = if has_parts {
+            let ids = inventories.values().copied().collect();
+            read_parts(dir, &ids)?
+        } else {
+            HashMap::new()
+        };
+
+        let mut sets = HashMap::new();
+        for set in read_csv::<SetRecord>(&dir.join("sets.csv"))? {
+            let mut path = vec![];
+            let mut theme_id = Some(set.theme_id);
+            // parents are bounded by the number of themes, in case of a cycle
+            while let Some(theme) = theme_id.and_then(|id| themes.get(&id))
+                && path.len() < themes.len()
+            {
+                path.push(theme.name.clone());
+                theme_id = theme.parent_id;
+            }
+            path.reverse();
+            let number = SetNumber::parse(&set.set_num);
+            let inventory_id = inventories.get(&number);
+            let minifigs = minifigs.as_ref().map(|m| {
+                inventory_id
+                    .and_then(|id| m.get(id))
+                    .copied()
+                    .unwrap_or_default()
+            });
+            let set = CatalogSet {
+                name: set.name,
+                parts: set.num_parts,
+                minifigs,
+                themes: path,
+                inventory: inventory_id
+                    .and_then(|id| parts.remove(id))
+                    .unwrap_or_default(),
+            };
+            sets.insert(number, Arc::new(set));
+        }
+        println!("read {} sets from catalog {}", sets.len(), dir.display());
+        Ok(Self { sets })
+    }
+
+    pub fn get(&self, number: &SetNumber) -> Option<Arc<CatalogSet>> {
+        self.sets.get(number).cloned()
+    }
+}
+
+/// The inventory id of each set's first inventory version
+fn read_inventories(dir: &Path) -> Result<HashMap<SetNumber, u32>> {
+    let mut inventories: HashMap<SetNumber, InventoryRecord> = HashMap::new();
+    for inventory in read_csv::<InventoryRecord>(&dir.join("inventories.csv"))? {
+        let number = SetNumber::parse(&inventory.set_num);
+        match inventories.get(&number) {
+            Some(existing) if existing.version <= inventory.version => {}
+            _ => {
+                inventories.insert(number, inventory);
+            }
+        }
+    }
+    Ok(inventories
+        .into_iter()
+        .map(|(number, inventory)| (number, inventory.id))
+        .collect())
+}
+
+/// Minifigure count of each inventory
+fn read_minifigs(dir: &Path) -> Result<HashMap<u32, u32>> {
+    let mut counts: HashMap<u32, u32> = HashMap::new();
+    for minifig in read_csv::<InventoryMinifigRecord>(&dir.join("inventory_minifigs.csv"))? {
+        *counts.entry(minifig.inventory_id).or_default() += minifig.quantity;
+    }
+    Ok(counts)
+}
+
+/// Parts of each of the inventories `ids`, by color and then part number,
+/// with spares last
+fn read_parts(dir: &Path, ids: &HashSet<u32>) -> Result<HashMap<u32, Vec<InventoryPart>>> {
+    let mut parts: HashMap<String, Arc<Part>> = read_csv::<PartRecord>(&dir.join("parts.csv"))?
+        .into_iter()
+        .map(|p| {
+            let part = Part {
+                number: p.part_num.clone(),
+                name: p.name,
+            };
+            (p.part_num, Arc::new(part))
+        })
+        .collect();
+    let colors: HashMap<i32, Arc<Color>> = read_csv::<ColorRecord>(&dir.join("colors.csv"))?
+        .into_iter()
+        .map(|c| {
+            let color = Color {
+                name: c.name,
+                rgb: c.rgb,
+            };
+            (c.id, Arc::new(color))
+        })
+        .collect();
+
+    let mut inventories: HashMap<u32, Vec<InventoryPart>> = HashMap::new();
+    let path = dir.join("inventory_parts.csv");
+    // streamed, since it lists every part of every set
+    for record in open_csv(&path)?.deserialize::<InventoryPartRecord>() {
+        let record = record.with_context(|| format!("invalid {}", path.display()))?;
+        if !ids.contains(&record.inventory_id) {
+            continue;
+        }
+        let Some(color) = colors.get(&record.color_id) else {
+            eprintln!(
+                "skipping part {} in unknown color {}",
+                record.part_num, record.color_id
+            );
+            continue;
+        };
+        // a part missing from parts.csv is still listed, by its number
+        let part = parts.entry(record.part_num.clone()).or_insert_with(|| {
+            Arc::new(Part {
+                number: record.part_num,
+                name: String::new(),
+            })
+        });
+        inventories
+            .entry(record.inventory_id)
+            .or_default()
+            .push(InventoryPart {
+                part: part.clone(),
+                color: color.clone(),
+                quantity: record.quantity,
+                spare: matches!(record.is_spare.as_str(), "t" | "True" | "true"),
+            });
+    }
+    for inventory in inventories.values_mut() {
+        inventory.sort_by(|a, b| {
+            (a.spare, &a.color.name, &a.part.number).cmp(&(b.spare, &b.color.name, &b.part.number))
+        });
+    }
+    Ok(inventories)
+}
+
+fn open_csv(path: &Path) -> Result<csv::Reader<fs::File>> {
+    csv::Reader::from_path(path).with_context(|| format!("failed to read {}", path.display()))
+}
+
+fn read_csv<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<Vec<T>> {
+    open_csv(path)?
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("invalid {}", path.display()))
+}
@@ -0,0 +1,362 @@
+use crate::FileSort;
+use crate::cache::IndexCache;
+use crate::transcode::Transcoder;
+use anyhow::{Context, Result, anyhow};
+use clap::Parser;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Serve cbz files from directory
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct CliArgs {
+    /// Config file [default: $XDG_CONFIG_HOME/lview/config.toml]
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Directory to serve, optionally labelled; repeat to serve several
+    #[arg(long = "dir", value_name = "[LABEL=]DIR")]
+    dirs: Vec<LibraryRoot>,
+
+    /// Address to listen on, optionally with a port; repeat to listen on several [default: 0.0.0.0]
+    #[arg(long)]
+    listen: Vec<String>,
+
+    /// Port to listen on [default: 3000]
+    #[arg(short, long)]
+    port: Option<u16>,
+
+    /// Index cache file [default: $XDG_STATE_HOME/lview/index.json]
+    #[arg(long)]
+    cache: Option<PathBuf>,
+
+    /// Use the index cache [default]
+    #[arg(long, overrides_with = "no_cache")]
+    use_cache: bool,
+
+    /// Rescan every file instead of using the index cache
+    #[arg(long, overrides_with = "use_cache")]
+    no_cache: bool,
+
+    /// Skip files and directories matching this pattern (gitignore syntax)
+    #[arg(long = "ignore", value_name = "GLOB")]
+    ignore_globs: Vec<String>,
+
+    /// Skip hidden files, NAS system folders and partial downloads [default]
+    #[arg(long, overrides_with = "no_default_ignores")]
+    default_ignores: bool,
+
+    /// Don't skip hidden files, NAS system folders and partial downloads
+    #[arg(long, overrides_with = "default_ignores")]
+    no_default_ignores: bool,
+
+    /// Follow symlinked files and directories [default]
+    #[arg(long, overrides_with = "no_follow_symlinks")]
+    follow_symlinks: bool,
+
+    /// Skip symlinked files and directories instead of following them
+    #[arg(long, overrides_with = "follow_symlinks")]
+    no_follow_symlinks: bool,
+
+    /// Sort order of the index when none is chosen, e.g. "number" or "-year"
+    #[arg(long)]
+    default_sort: Option<FileSort>,
+
+    /// Convert pages in formats the browser can't show [default]
+    #[arg(long, overrides_with = "no_transcode")]
+    transcode: bool,
+
+    /// Serve pages as they are, even in formats the browser can't show
+    #[arg(long, overrides_with = "transcode")]
+    no_transcode: bool,
+
+    /// Directory to keep converted pages in [default: $XDG_CACHE_HOME/lview/pages]
+    #[arg(long, value_name = "DIR")]
+    page_cache: Option<PathBuf>,
+
+    /// Directory of Rebrickable CSV dumps (sets.csv, themes.csv) to add set details from
+    #[arg(long, value_name = "DIR")]
+    catalog: Option<PathBuf>,
+}
+
+/// Settings read from the config file. Anything given on the command line
+/// takes precedence.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Config {
+    library: Vec<LibraryRoot>,
+    listen: Vec<String>,
+    port: Option<u16>,
+    cache: Option<PathBuf>,
+    use_cache: Option<bool>,
+    ignore: Vec<String>,
+    default_ignores: Option<bool>,
+    follow_symlinks: Option<bool>,
+    default_sort: Option<FileSort>,
+    transcode: Option<bool>,
+    page_cache: Option<PathBuf>,
+    catalog: Option<PathBuf>,
+}
+
+impl Config {
+    fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|d| d.join("lview").join("config.toml"))
+    }
+
+    /// Read the config file at `path`, or from the default location if it
+    /// exists.
+    fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match Self::default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+        let data = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read config {}", path.display()))?;
+        toml::from_str(&data).with_context(|| format!("invalid config {}", path.display()))
+    }
+}
+
+/// A library directory and its optional label
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LibraryRoot {
+    label: Option<String>,
+    dir: PathBuf,
+}
+
+impl FromStr for LibraryRoot {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((label, dir)) if !label.is_empty() && !label.contains(['/', '\\']) => Ok(Self {
+                label: Some(label.to_string()),
+                dir: dir.into(),
+            }),
+            _ => Ok(Self {
+                label: None,
+                dir: s.into(),
+            }),
+        }
+    }
+}
+
+/// Work out a unique label for each root. A single unlabelled root gets an
+/// empty label, so its URLs don't change.
+fn label_roots(roots: Vec<LibraryRoot>) -> Result<Vec<(String, PathBuf)>> {
+    if let [LibraryRoot { label: None, dir }] = roots.as_slice() {
+        return Ok(vec![(String::new(), dir.clone())]);
+    }
+    let mut labelled: Vec<(String, PathBuf)> = vec![];
+    for root in roots {
+        let label = match root.label {
+            Some(label) => label,
+            None => root
+                .dir
+                .file_name()
+                .context("can't derive a label from the directory name")?
+                .to_string_lossy()
+                .into_owned(),
+        };
+        // labels are the first segment of URL paths
+        if label.is_empty() || label.contains(['/', '\\']) {
+            return Err(anyhow!(
+                "invalid library label '{}', labels can't be empty or contain '/' or '\\'",
+                label
+            ));
+        }
+        if labelled.iter().any(|(l, _)| *l == label) {
+            return Err(anyhow!(
+                "duplicate library label '{}', give each library its own label",
+                label
+            ));
+        }
+        labelled.push((label, root.dir));
+    }
+    Ok(labelled)
+}
+
+/// A setting given on the command line with a pair of flags like
+/// `--transcode` and `--no-transcode`, of which the last one given wins
+fn flag(on: bool, off: bool) -> Option<bool> {
+    match (on, off) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_listen(listen: &str, port: u16) -> Result<SocketAddr> {
+    if let Ok(addr) = SocketAddr::from_str(listen) {
+        return Ok(addr);
+    }
+    let ip =
+        IpAddr::from_str(listen).with_context(|| format!("invalid listen address '{}'", listen))?;
+    Ok(SocketAddr::from((ip, port)))
+}
+
+/// Settings from the command line and config file combined
+#[derive(Debug)]
+pub struct Settings {
+    /// Labelled library roots
+    pub roots: Vec<(String, PathBuf)>,
+    pub listen: Vec<SocketAddr>,
+    pub cache_path: Option<PathBuf>,
+    pub ignore_globs: Vec<String>,
+    pub default_ignores: bool,
+    pub follow_symlinks: bool,
+    pub default_sort: FileSort,
+    pub transcode: bool,
+    pub page_cache: Option<PathBuf>,
+    pub catalog: Option<PathBuf>,
+}
+
+impl Settings {
+    pub fn load() -> Result<Self> {
+        let args = CliArgs::parse();
+        let config = Config::load(args.config.as_deref())?;
+
+        let mut roots = if args.dirs.is_empty() {
+            config.library
+        } else {
+            args.dirs
+        };
+        if roots.is_empty() {
+            roots.push(LibraryRoot {
+                label: None,
+                dir: env::current_dir()?,
+            });
+        }
+
+        let port = args.port.or(config.port).unwrap_or(3000);
+        let mut listen = if args.listen.is_empty() {
+            config.listen
+        } else {
+            args.listen
+        };
+        if listen.is_empty() {
+            listen.push("0.0.0.0".into());
+        }
+
+        let use_cache = flag(args.use_cache, args.no_cache).or(config.use_cache);
+        let cache_path = if !use_cache.unwrap_or(true) {
+            None
+        } else {
+            args.cache
+                .or(config.cache)
+                .or_else(IndexCache::default_path)
+        };
+
+        Ok(Self {
+            roots: label_roots(roots)?,
+            listen: listen
+                .iter()
+                .map(|l| parse_listen(l, port))
+                .collect::<Result<_>>()?,
+            cache_path,
+            ignore_globs: if args.ignore_globs.is_empty() {
+                config.ignore
+            } else {
+                args.ignore_globs
+            },
+            default_ignores: flag(args.default_ignores, args.no_default_ignores)
+                .or(config.default_ignores)
+                .unwrap_or(true),
+            follow_symlinks: flag(args.follow_symlinks, args.no_follow_symlinks)
+                .or(config.follow_symlinks)
+                .unwrap_or(true),
+            default_sort: args
+                .default_sort
+                .or(config.default_sort)
+                .unwrap_or_default(),
+            transcode: flag(args.transcode, args.no_transcode)
+                .or(config.transcode)
+                .unwrap_or(true),
+            page_cache: args
+                .page_cache
+                .or(config.page_cache)
+                .or_else(Transcoder::default_cache_dir),
+            catalog: args.catalog.or(config.catalog),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roots(config: &str) -> Result<Vec<(String, PathBuf)>> {
+        label_roots(toml::from_str::<Config>(config)?.library)
+    }
+
+    #[test]
+    fn test_label_roots() {
+        assert_eq!(
+            label_roots(vec!["/mnt/lego".parse().unwrap()]).unwrap(),
+            [(String::new(), PathBuf::from("/mnt/lego"))]
+        );
+        assert_eq!(
+            label_roots(vec![
+                "/mnt/ssd/lego".parse().unwrap(),
+                "old=/mnt/nas/lego".parse().unwrap(),
+            ])
+            .unwrap(),
+            [
+                ("lego".to_string(), PathBuf::from("/mnt/ssd/lego")),
+                ("old".to_string(), PathBuf::from("/mnt/nas/lego")),
+            ]
+        );
+        assert!(
+            label_roots(vec![
+                "/mnt/ssd/lego".parse().unwrap(),
+                "/mnt/nas/lego".parse().unwrap(),
+            ])
+            .is_err()
+        );
+        assert!(label_roots(vec!["/".parse().unwrap(), "/mnt".parse().unwrap()]).is_err());
+
+        // a label with a slash is part of the path on the command line
+        let root: LibraryRoot = "a/b=/mnt/lego".parse().unwrap();
+        assert_eq!(root.label, None);
+        assert_eq!(root.dir, PathBuf::from("a/b=/mnt/lego"));
+
+        assert!(roots("[[library]]\nlabel = \"a/b\"\ndir = \"/mnt/lego\"").is_err());
+        assert!(roots("[[library]]\nlabel = \"a\\\\b\"\ndir = \"/mnt/lego\"").is_err());
+        assert!(roots("[[library]]\nlabel = \"\"\ndir = \"/mnt/lego\"").is_err());
+        assert_eq!(
+            roots("[[library]]\nlabel = \"current\"\ndir = \"/mnt/lego\"").unwrap(),
+            [("current".to_string(), PathBuf::from("/mnt/lego"))]
+        );
+    }
+
+    #[test]
+    fn test_flag() {
+        let transcode = |args: &[&str]| {
+            let args = CliArgs::try_parse_from([&["lview"], args].concat()).unwrap();
+            flag(args.transcode, args.no_transcode)
+        };
+        assert_eq!(transcode(&[]), None);
+        assert_eq!(transcode(&["--transcode"]), Some(true));
+        assert_eq!(transcode(&["--no-transcode"]), Some(false));
+        assert_eq!(transcode(&["--no-transcode", "--transcode"]), Some(true));
+        assert_eq!(transcode(&["--transcode", "--no-transcode"]), Some(false));
+    }
+
+    #[test]
+    fn test_deny_unknown_fields() {
+        assert!(toml::from_str::<Config>("port = 3000\ncatalog = \"/mnt/rebrickable\"").is_ok());
+        assert!(toml::from_str::<Config>("prot = 3000").is_err());
+        assert!(toml::from_str::<Config>("use-cache = false").is_ok());
+        assert!(toml::from_str::<Config>("no-cache = true").is_err());
+        assert!(
+            toml::from_str::<Config>("[[library]]\ndir = \"/mnt/lego\"\nname = \"x\"").is_err()
+        );
+    }
+}
@@ -0,0 +1,528 @@
+use crate::epub::Epub;
+use crate::{ComicInfo, image_type};
+use anyhow::{Context, Result, anyhow};
+use chrono::NaiveDateTime;
+use pdf::file::{CachedFile, FileOptions};
+use pdf::object::Resolve;
+use sevenz_rust2::{ArchiveReader, Password};
+use std::cmp::Ordering;
+use std::fs;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use xmp_toolkit::{XmpMeta, XmpValue, xmp_ns};
+use zip::ZipArchive;
+
+/// A page image read from a document
+pub struct Page {
+    pub data: Vec<u8>,
+    pub modified: SystemTime,
+}
+
+/// A format the library can serve. Paged documents list their page images,
+/// which the viewer shows one at a time; others are handed to the browser
+/// whole.
+pub trait Document: Send {
+    /// Metadata stored in or next to the document
+    fn comic_info(&mut self) -> Result<Option<ComicInfo>>;
+
+    /// The same metadata as separate layers, in order of precedence, for
+    /// documents that store it in more than one place. A layer that can't be
+    /// read is an error, without hiding the others.
+    fn comic_info_layers(&mut self) -> Vec<Result<ComicInfo>> {
+        self.comic_info().transpose().into_iter().collect()
+    }
+
+    /// Names of the page images, in any order
+    fn page_names(&mut self) -> Result<Vec<String>>;
+
+    /// Names of the page images, in reading order: as listed in the
+    /// `<Pages>` of `info`, the file's metadata, otherwise in natural order.
+    /// The metadata is passed in rather than read again, since it has
+    /// already been parsed when the file was scanned.
+    fn pages(&mut self, info: Option<&ComicInfo>) -> Result<Vec<String>> {
+        let mut pages = self.page_names()?;
+        pages.sort_by(|a, b| natural_cmp(a, b));
+        match info {
+            Some(info) => Ok(info.order_pages(pages)),
+            None => Ok(pages),
+        }
+    }
+
+    fn read_page(&mut self, name: &str) -> Result<Page>;
+
+    fn page_count(&mut self, info: Option<&ComicInfo>) -> Result<usize> {
+        Ok(self.pages(info)?.len())
+    }
+
+    /// Content type of the document itself, if the browser shows it whole
+    /// instead of page by page
+    fn whole_content_type(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+type Opener = fn(&Path) -> Result<Box<dyn Document>>;
+
+/// Supported file extensions and how to open each
+const FORMATS: &[(&str, Opener)] = &[
+    ("cbz", |path| Ok(Box::new(Cbz::open(path)?))),
+    ("pdf", |path| Ok(Box::new(Pdf::open(path)))),
+    ("cb7", |path| Ok(Box::new(Cb7::open(path)?))),
+    ("cbt", |path| Ok(Box::new(Cbt::open(path)))),
+    ("epub", |path| Ok(Box::new(Epub::open(path)?))),
+];
+
+/// Open the document at `path`, picking the format by extension
+pub fn open(path: &Path) -> Result<Box<dyn Document>> {
+    if path.is_dir() {
+        return Ok(Box::new(ImageDir::open(path)));
+    }
+    let ext = path.extension().context("Unsupported file extension")?;
+    let (_, open) = FORMATS
+        .iter()
+        .find(|(e, _)| ext == *e)
+        .context("Unsupported file extension")?;
+    open(path)
+}
+
+/// Whether `path` is a document that can be served
+pub fn is_supported(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| FORMATS.iter().any(|(e, _)| ext == *e))
+        || is_image_dir(path)
+}
+
+/// Compare names so numbers in them sort by value, putting `page2.jpg`
+/// before `page10.jpg`.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chunks = chunks(a);
+    let mut b_chunks = chunks(b);
+    // "02" and "2" are only told apart if the names are otherwise equal
+    let mut zeros = Ordering::Equal;
+    loop {
+        let (a, b) = match (a_chunks.next(), b_chunks.next()) {
+            (Some(a), Some(b)) => (a, b),
+            (a, b) => return a.is_some().cmp(&b.is_some()).then(zeros),
+        };
+        let numeric = |s: &str| s.starts_with(|c: char| c.is_ascii_digit());
+        let order = if numeric(a) && numeric(b) {
+            let (a_value, b_value) = (a.trim_start_matches('0'), b.trim_start_matches('0'));
+            zeros = zeros.then_with(|| b.len().cmp(&a.len()));
+            a_value
+                .len()
+                .cmp(&b_value.len())
+                .then_with(|| a_value.cmp(b_value))
+        } else {
+            a.cmp(b)
+        };
+        if order.is_ne() {
+            return order;
+        }
+    }
+}
+
+/// Split a name into runs of digits and non-digits
+fn chunks(s: &str) -> impl Iterator<Item = &str> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        let numeric = rest.starts_with(|c: char| c.is_ascii_digit());
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != numeric)
+            .unwrap_or(rest.len());
+        let (chunk, tail) = rest.split_at(end);
+        rest = tail;
+        (!chunk.is_empty()).then_some(chunk)
+    })
+}
+
+pub fn should_expose(filename: &str) -> bool {
+    image_type::from_name(filename).is_some()
+}
+
+pub fn read_comic_info(reader: impl Read) -> Result<ComicInfo> {
+    quick_xml::de::from_reader(BufReader::new(reader)).context("invalid ComicInfo.xml")
+}
+
+fn modified_from_zip(date: Option<zip::DateTime>) -> Result<SystemTime> {
+    let date = date.context("missing last-modified")?;
+    let date = NaiveDateTime::try_from(date)?;
+    Ok(date.and_utc().into())
+}
+
+/// Read the entry `name` of a zip archive as a page
+pub fn read_zip_page<R: Read + io::Seek>(zip: &mut ZipArchive<R>, name: &str) -> Result<Page> {
+    let mut page = zip.by_name(name)?;
+    let mut data = vec![];
+    page.read_to_end(&mut data)?;
+    Ok(Page {
+        data,
+        modified: modified_from_zip(page.last_modified())?,
+    })
+}
+
+struct Cbz {
+    zip: ZipArchive<fs::File>,
+}
+
+impl Cbz {
+    fn open(path: &Path) -> Result<Self> {
+        Ok(Self {
+            zip: ZipArchive::new(fs::File::open(path)?)?,
+        })
+    }
+}
+
+impl Document for Cbz {
+    fn comic_info(&mut self) -> Result<Option<ComicInfo>> {
+        match self.zip.by_name("ComicInfo.xml") {
+            Ok(info_xml) => read_comic_info(info_xml).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn page_names(&mut self) -> Result<Vec<String>> {
+        let pages: Vec<String> = self
+            .zip
+            .file_names()
+            .filter(|f| should_expose(f))
+            .map(String::from)
+            .collect();
+        Ok(pages)
+    }
+
+    fn read_page(&mut self, name: &str) -> Result<Page> {
+        read_zip_page(&mut self.zip, name)
+    }
+}
+
+/// PDFs are shown by the browser's own viewer, so pages aren't listed.
+struct Pdf {
+    path: PathBuf,
+    /// Parsed on first use, since serving the file doesn't need it
+    pdf_document: Option<CachedFile<Vec<u8>>>,
+}
+
+impl Pdf {
+    fn open(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            pdf_document: None,
+        }
+    }
+
+    fn pdf_document(&mut self) -> Result<&CachedFile<Vec<u8>>> {
+        if self.pdf_document.is_none() {
+            self.pdf_document = Some(FileOptions::cached().open(&self.path)?);
+        }
+        Ok(self.pdf_document.as_ref().unwrap())
+    }
+
+    /// Metadata from the XMP stream embedded in the PDF
+    fn embedded_xmp(&mut self) -> Result<Option<XmpMeta>> {
+        let pdf_document = self.pdf_document()?;
+        let Some(metadata) = pdf_document.get_root().metadata else {
+            return Ok(None);
+        };
+        let resolver = pdf_document.resolver();
+        let data = resolver.get(metadata)?.data(&resolver)?;
+        Ok(Some(XmpMeta::from_str(&String::from_utf8_lossy(&data))?))
+    }
+
+    /// Metadata from the document information dictionary, in XMP terms, if
+    /// it has any of the fields used. Producer and dates alone say nothing
+    /// about the instructions.
+    fn info_dict_xmp(&mut self) -> Result<Option<XmpMeta>> {
+        let Some(info) = &self.pdf_document()?.trailer.info_dict else {
+            return Ok(None);
+        };
+        if info.title.is_none()
+            && info.subject.is_none()
+            && info.keywords.is_none()
+            && info.creation_date.is_none()
+        {
+            return Ok(None);
+        }
+        let mut xmp = XmpMeta::new()?;
+        if let Some(title) = &info.title {
+            xmp.set_localized_text(
+                xmp_ns::DC,
+                "title",
+                None,
+                "x-default",
+                &title.to_string_lossy(),
+            )?;
+        }
+        if let Some(subject) = &info.subject {
+            xmp.set_localized_text(
+                xmp_ns::DC,
+                "description",
+                None,
+                "x-default",
+                &subject.to_string_lossy(),
+            )?;
+        }
+        let keywords = info.keywords.as_ref().map(|k| k.to_string_lossy());
+        for keyword in keywords.iter().flat_map(|k| k.split([',', ';'])) {
+            let keyword = keyword.trim();
+            if !keyword.is_empty() {
+                let array = XmpValue::from("subject").set_is_array(true);
+                xmp.append_array_item(xmp_ns::DC, &array, &keyword.into())?;
+            }
+        }
+        if let Some(date) = &info.creation_date {
+            let date = format!("{:04}-{:02}-{:02}", date.year, date.month, date.day);
+            let array = XmpValue::from("date")
+                .set_is_array(true)
+                .set_is_ordered(true);
+            xmp.append_array_item(xmp_ns::DC, &array, &date.as_str().into())?;
+        }
+        Ok(Some(xmp))
+    }
+}
+
+impl Document for Pdf {
+    /// Both layers merged, leaving out any that can't be read
+    fn comic_info(&mut self) -> Result<Option<ComicInfo>> {
+        Ok(self
+            .comic_info_layers()
+            .into_iter()
+            .flatten()
+            .reduce(ComicInfo::or))
+    }
+
+    /// Embedded XMP takes precedence over the information dictionary.
+    fn comic_info_layers(&mut self) -> Vec<Result<ComicInfo>> {
+        let embedded = self.embedded_xmp().context("invalid embedded XMP");
+        let info_dict = self
+            .info_dict_xmp()
+            .context("invalid information dictionary");
+        [embedded, info_dict]
+            .into_iter()
+            .filter_map(Result::transpose)
+            .map(|xmp| xmp.map(|xmp| ComicInfo::from_xmp(&xmp)))
+            .collect()
+    }
+
+    fn page_names(&mut self) -> Result<Vec<String>> {
+        Ok(vec![])
+    }
+
+    fn read_page(&mut self, name: &str) -> Result<Page> {
+        Err(anyhow!("missing page {}", name))
+    }
+
+    fn page_count(&mut self, _info: Option<&ComicInfo>) -> Result<usize> {
+        Ok(self.pdf_document()?.num_pages() as usize)
+    }
+
+    fn whole_content_type(&self) -> Option<&'static str> {
+        Some("application/pdf")
+    }
+}
+
+/// A folder of page images
+struct ImageDir {
+    path: PathBuf,
+}
+
+impl ImageDir {
+    fn open(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl Document for ImageDir {
+    fn comic_info(&mut self) -> Result<Option<ComicInfo>> {
+        let info_path = self.path.join("ComicInfo.xml");
+        if info_path.exists() {
+            fs::File::open(info_path)
+                .map_err(anyhow::Error::from)
+                .and_then(read_comic_info)
+                .map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn page_names(&mut self) -> Result<Vec<String>> {
+        let mut pages = vec![];
+        for entry in fs::read_dir(&self.path)? {
+            if let Ok(name) = entry?.file_name().into_string()
+                && !name.starts_with('.')
+                && should_expose(&name)
+            {
+                pages.push(name);
+            }
+        }
+        Ok(pages)
+    }
+
+    fn read_page(&mut self, name: &str) -> Result<Page> {
+        let path = self.path.join(name);
+        Ok(Page {
+            modified: fs::metadata(&path)?.modified()?,
+            data: fs::read(&path)?,
+        })
+    }
+}
+
+/// Whether `path` is a directory holding nothing but page images, plus
+/// optionally a `ComicInfo.xml` and hidden files.
+pub fn is_image_dir(path: &Path) -> bool {
+    let Ok(entries) = fs::read_dir(path) else {
+        return false;
+    };
+    let mut has_images = false;
+    for entry in entries {
+        let Ok(entry) = entry else {
+            return false;
+        };
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') || name == "ComicInfo.xml" {
+            continue;
+        }
+        if !entry.path().is_file() || !should_expose(&name) {
+            return false;
+        }
+        has_images = true;
+    }
+    has_images
+}
+
+struct Cb7 {
+    archive: ArchiveReader<fs::File>,
+    modified: SystemTime,
+}
+
+impl Cb7 {
+    fn open(path: &Path) -> Result<Self> {
+        Ok(Self {
+            archive: ArchiveReader::open(path, Password::empty())?,
+            modified: fs::metadata(path)?.modified()?,
+        })
+    }
+}
+
+impl Document for Cb7 {
+    fn comic_info(&mut self) -> Result<Option<ComicInfo>> {
+        let entries = &self.archive.archive().files;
+        if entries.iter().any(|e| e.name == "ComicInfo.xml") {
+            let data = self.archive.read_file("ComicInfo.xml")?;
+            read_comic_info(data.as_slice()).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn page_names(&mut self) -> Result<Vec<String>> {
+        let pages: Vec<String> = self
+            .archive
+            .archive()
+            .files
+            .iter()
+            .filter(|e| !e.is_directory && should_expose(&e.name))
+            .map(|e| e.name.clone())
+            .collect();
+        Ok(pages)
+    }
+
+    fn read_page(&mut self, name: &str) -> Result<Page> {
+        let modified = self
+            .archive
+            .archive()
+            .files
+            .iter()
+            .find(|e| e.name == name && e.has_last_modified_date)
+            .map_or(self.modified, |e| e.last_modified_date.into());
+        Ok(Page {
+            data: self.archive.read_file(name)?,
+            modified,
+        })
+    }
+}
+
+/// tar has no index, so every read goes through the archive from the start.
+struct Cbt {
+    path: PathBuf,
+}
+
+impl Cbt {
+    fn open(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+
+    fn archive(&self) -> Result<tar::Archive<fs::File>> {
+        Ok(tar::Archive::new(fs::File::open(&self.path)?))
+    }
+}
+
+impl Document for Cbt {
+    fn comic_info(&mut self) -> Result<Option<ComicInfo>> {
+        for entry in self.archive()?.entries()? {
+            let entry = entry?;
+            if entry.path()?.as_os_str() == "ComicInfo.xml" {
+                return read_comic_info(entry).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    fn page_names(&mut self) -> Result<Vec<String>> {
+        let mut pages = vec![];
+        for entry in self.archive()?.entries()? {
+            let entry = entry?;
+            let name = entry.path()?.to_string_lossy().into_owned();
+            if entry.header().entry_type().is_file() && should_expose(&name) {
+                pages.push(name);
+            }
+        }
+        Ok(pages)
+    }
+
+    fn read_page(&mut self, name: &str) -> Result<Page> {
+        for entry in self.archive()?.entries()? {
+            let mut entry = entry?;
+            if entry.path()?.to_string_lossy() == name {
+                let modified = UNIX_EPOCH + Duration::from_secs(entry.header().mtime()?);
+                let mut data = vec![];
+                entry.read_to_end(&mut data)?;
+                return Ok(Page { data, modified });
+            }
+        }
+        Err(anyhow!("missing page {}", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_natural_cmp() {
+        let mut pages = vec![
+            "page10.jpg",
+            "page2.jpg",
+            "page02b.jpg",
+            "Page1.jpg",
+            "page1.jpg",
+        ];
+        pages.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            pages,
+            [
+                "Page1.jpg",
+                "page1.jpg",
+                "page2.jpg",
+                "page02b.jpg",
+                "page10.jpg"
+            ]
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use crate::ComicInfo;
+use crate::document::{Document, Page, read_zip_page, should_expose};
+use crate::release_date::ReleaseDate;
+use anyhow::{Context, Result, anyhow};
+use percent_encoding::percent_decode_str;
+use quick_xml::Reader;
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::{BytesStart, Event};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Seek};
+use std::path::Path;
+use zip::ZipArchive;
+
+/// A fixed-layout EPUB, paged through like a cbz
+pub struct Epub {
+    zip: ZipArchive<fs::File>,
+    package: Package,
+}
+
+impl Epub {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut zip = ZipArchive::new(fs::File::open(path)?)?;
+        let package = read_package(&mut zip)?;
+        Ok(Self { zip, package })
+    }
+}
+
+impl Document for Epub {
+    fn comic_info(&mut self) -> Result<Option<ComicInfo>> {
+        Ok(Some(self.package.info.clone()))
+    }
+
+    fn page_names(&mut self) -> Result<Vec<String>> {
+        Ok(self.package.pages.clone())
+    }
+
+    /// The spine gives the reading order
+    fn pages(&mut self, _info: Option<&ComicInfo>) -> Result<Vec<String>> {
+        self.page_names()
+    }
+
+    fn read_page(&mut self, name: &str) -> Result<Page> {
+        read_zip_page(&mut self.zip, name)
+    }
+}
+
+/// What's needed from an EPUB package: its metadata and page images.
+struct Package {
+    info: ComicInfo,
+    /// Page image paths within the archive, in spine order
+    pages: Vec<String>,
+}
+
+/// Read the package document of a fixed-layout EPUB.
+///
+/// Each spine item is either an image or an XHTML page wrapping one; pages
+/// without an image are left out.
+fn read_package<R: Read + Seek>(zip: &mut ZipArchive<R>) -> Result<Package> {
+    let container = read_string(zip, "META-INF/container.xml")?;
+    let opf_path = find_attribute(&container, b"rootfile", b"full-path")?
+        .context("no rootfile in META-INF/container.xml")?;
+    let opf = read_string(zip, &opf_path)?;
+
+    let mut info = ComicInfo::default();
+    let mut manifest = HashMap::new();
+    let mut spine = vec![];
+    let mut reader = Reader::from_str(&opf);
+    let mut in_metadata = false;
+    let mut field: Option<Vec<u8>> = None;
+    let mut text = String::new();
+    loop {
+        match reader.read_event().context("invalid package document")? {
+            Event::Start(e) if e.local_name().as_ref() == b"metadata" => in_metadata = true,
+            Event::End(e) if e.local_name().as_ref() == b"metadata" => in_metadata = false,
+            Event::Start(e) if in_metadata => {
+                field = Some(e.local_name().as_ref().to_vec());
+                text.clear();
+            }
+            Event::Text(e) if field.is_some() => text.push_str(&e.decode()?),
+            Event::GeneralRef(e) if field.is_some() => {
+                if let Some(c) = e.resolve_char_ref()? {
+                    text.push(c);
+                } else if let Some(s) = resolve_predefined_entity(&e.decode()?) {
+                    text.push_str(s);
+                }
+            }
+            Event::End(_) if field.is_some() => {
+                let value = text.trim().to_string();
+                match field.take().as_deref() {
+                    Some(b"title") if info.title.is_empty() => info.title = value,
+                    Some(b"identifier") if info.number.is_empty() => info.number = value,
+                    Some(b"publisher") if info.publisher.is_empty() => info.publisher = value,
+                    Some(b"date") if info.year.is_none() => {
+                        if let Ok(date) = value.parse::<ReleaseDate>() {
+                            info.year = Some(date.year);
+                            info.month = date.month;
+                            info.day = date.day;
+                        }
+                    }
+                    Some(b"subject") => info.genre.push(value),
+                    Some(b"language") if info.language_iso.is_empty() => info.language_iso = value,
+                    Some(b"description") if info.summary.is_empty() => info.summary = value,
+                    _ => {}
+                }
+            }
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"item" => {
+                if let (Some(id), Some(href), media_type) = (
+                    attribute(&e, b"id")?,
+                    attribute(&e, b"href")?,
+                    attribute(&e, b"media-type")?,
+                ) {
+                    manifest.insert(id, (resolve(&opf_path, &href), media_type));
+                }
+            }
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"itemref" => {
+                spine.extend(attribute(&e, b"idref")?);
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    let mut pages = vec![];
+    for idref in spine {
+        let Some((path, media_type)) = manifest.get(&idref) else {
+            return Err(anyhow!("spine item '{}' not in manifest", idref));
+        };
+        if media_type
+            .as_deref()
+            .is_some_and(|t| t.starts_with("image/"))
+        {
+            pages.push(path.clone());
+            continue;
+        }
+        let page = read_string(zip, path)?;
+        let image = match find_attribute(&page, b"img", b"src")? {
+            Some(src) => Some(src),
+            None => find_attribute(&page, b"image", b"href")?,
+        };
+        if let Some(image) = image {
+            pages.push(resolve(path, &image));
+        }
+    }
+    pages.retain(|p| should_expose(p));
+    if pages.is_empty() {
+        return Err(anyhow!("no page images in spine"));
+    }
+
+    Ok(Package { info, pages })
+}
+
+fn read_string<R: Read + Seek>(zip: &mut ZipArchive<R>, name: &str) -> Result<String> {
+    let mut data = String::new();
+    zip.by_name(name)
+        .with_context(|| format!("missing {}", name))?
+        .read_to_string(&mut data)?;
+    Ok(data)
+}
+
+/// Value of the attribute with local name `name`, ignoring any namespace
+fn attribute(element: &BytesStart, name: &[u8]) -> Result<Option<String>> {
+    for attribute in element.attributes() {
+        let attribute = attribute?;
+        if attribute.key.local_name().as_ref() == name {
+            return Ok(Some(attribute.unescape_value()?.into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+/// Attribute `name` of the first `element` in the document
+fn find_attribute(xml: &str, element: &[u8], name: &[u8]) -> Result<Option<String>> {
+    let mut reader = Reader::from_str(xml);
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == element => {
+                if let Some(value) = attribute(&e, name)? {
+                    return Ok(Some(value));
+                }
+            }
+            Event::Eof => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
+/// Resolve `href` relative to the archive path `base`
+fn resolve(base: &str, href: &str) -> String {
+    let href = href.split(['#', '?']).next().unwrap_or_default();
+    let href = percent_decode_str(href).decode_utf8_lossy();
+    let mut parts: Vec<&str> = base.split('/').collect();
+    parts.pop();
+    for part in href.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(
+            resolve("OEBPS/content.opf", "images/p1.jpg"),
+            "OEBPS/images/p1.jpg"
+        );
+        assert_eq!(
+            resolve("OEBPS/text/p1.xhtml", "../images/p1.jpg"),
+            "OEBPS/images/p1.jpg"
+        );
+        assert_eq!(resolve("content.opf", "./p%201.jpg#top"), "p 1.jpg");
+        assert_eq!(resolve("OEBPS/content.opf", "p1.jpg?v=2"), "OEBPS/p1.jpg");
+        assert_eq!(resolve("content.opf", "../../p1.jpg"), "p1.jpg");
+    }
+}
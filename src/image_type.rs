@@ -0,0 +1,97 @@
+/// An image format that can be shown as a page
+#[derive(Debug)]
+pub struct ImageType {
+    pub content_type: &'static str,
+    extensions: &'static [&'static str],
+    /// Whether data starts with this format's signature
+    matches: fn(&[u8]) -> bool,
+}
+
+const IMAGE_TYPES: &[ImageType] = &[
+    ImageType {
+        content_type: "image/jpeg",
+        extensions: &["jpg", "jpeg", "jpe", "jfif"],
+        matches: |data| data.starts_with(&[0xff, 0xd8, 0xff]),
+    },
+    ImageType {
+        content_type: "image/png",
+        extensions: &["png"],
+        matches: |data| data.starts_with(b"\x89PNG\r\n\x1a\n"),
+    },
+    ImageType {
+        content_type: "image/gif",
+        extensions: &["gif"],
+        matches: |data| data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a"),
+    },
+    ImageType {
+        content_type: "image/webp",
+        extensions: &["webp"],
+        matches: |data| data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP"),
+    },
+    ImageType {
+        content_type: "image/tiff",
+        extensions: &["tif", "tiff"],
+        matches: |data| data.starts_with(b"II*\0") || data.starts_with(b"MM\0*"),
+    },
+    ImageType {
+        content_type: "image/avif",
+        extensions: &["avif"],
+        matches: |data| {
+            data.get(4..8) == Some(b"ftyp")
+                && matches!(data.get(8..12), Some(b"avif") | Some(b"avis"))
+        },
+    },
+    ImageType {
+        content_type: "image/jxl",
+        extensions: &["jxl"],
+        matches: |data| {
+            data.starts_with(&[0xff, 0x0a]) || data.starts_with(b"\0\0\0\x0cJXL \r\n\x87\n")
+        },
+    },
+];
+
+/// The image type of a file name, going by its extension
+pub fn from_name(name: &str) -> Option<&'static ImageType> {
+    let (_, ext) = name.rsplit_once('.')?;
+    IMAGE_TYPES
+        .iter()
+        .find(|t| t.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+}
+
+/// The image type of some data, going by its signature
+pub fn sniff(data: &[u8]) -> Option<&'static ImageType> {
+    IMAGE_TYPES.iter().find(|t| (t.matches)(data))
+}
+
+/// Content type of the page `name`, trusting the data over the name since
+/// archives are often assembled carelessly
+pub fn content_type(name: &str, data: &[u8]) -> &'static str {
+    sniff(data)
+        .or_else(|| from_name(name))
+        .map_or("application/octet-stream", |t| t.content_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sniffed(data: &[u8]) -> Option<&'static str> {
+        sniff(data).map(|t| t.content_type)
+    }
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniffed(&[0xff, 0xd8, 0xff, 0xe0]), Some("image/jpeg"));
+        assert_eq!(sniffed(b"\x89PNG\r\n\x1a\n\0"), Some("image/png"));
+        assert_eq!(sniffed(b"GIF89a"), Some("image/gif"));
+        assert_eq!(sniffed(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniffed(b"RIFF\0\0\0\0WAVEfmt "), None);
+        assert_eq!(sniffed(b"MM\0*\0\0\0\x08"), Some("image/tiff"));
+        assert_eq!(sniffed(b"\0\0\0\x1cftypavif"), Some("image/avif"));
+        assert_eq!(sniffed(b"\0\0\0\x1cftypheic"), None);
+        assert_eq!(sniffed(&[0xff, 0x0a, 0x00]), Some("image/jxl"));
+        assert_eq!(sniffed(b"\0\0\0\x0cJXL \r\n\x87\n"), Some("image/jxl"));
+        assert_eq!(sniffed(b"<svg"), None);
+        assert_eq!(sniffed(b""), None);
+    }
+}
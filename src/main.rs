@@ -1,225 +1,564 @@
-use anyhow::{Context, Result, anyhow};
+use anyhow::{Context, Result};
 use axum::body::Body;
 use axum::debug_handler;
-use axum::extract::Query;
-use axum::http::{StatusCode, header};
-use axum::response::{IntoResponse, Response};
-use axum::{Router, extract::State, response::Html, routing::get};
-use chrono::NaiveDateTime;
-use clap::Parser;
+use axum::extract::{self, Query};
+use axum::http::{HeaderMap, StatusCode, Uri, header};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::{Json, Router, extract::State, response::Html, routing::get};
 use httpdate::fmt_http_date;
-use pdf;
-use percent_encoding::{NON_ALPHANUMERIC, PercentEncode, utf8_percent_encode};
+use percent_encoding::{
+    NON_ALPHANUMERIC, PercentEncode, percent_decode_str, percent_encode, utf8_percent_encode,
+};
 use sailfish::TemplateSimple;
 use serde::{Deserialize, Serialize};
 use serde_with::formats::CommaSeparator;
 use serde_with::{DeserializeFromStr, NoneAsEmptyString, StringWithSeparator, serde_as};
-use std::collections::BTreeSet;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt::{Display, Formatter};
-use std::io::{BufReader, Read};
-use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
-use std::{env, fmt, fs, io};
+use std::{fmt, fs, io};
 use tokio::net::TcpListener;
 use tokio::sync::RwLock;
+use tokio::task::JoinSet;
 use tokio_util::io::ReaderStream;
 use tower_http::services::ServeDir;
 use xmp_toolkit::XmpMeta;
 use xmp_toolkit::xmp_ns;
-use zip::ZipArchive;
 
-/// Serve cbz files from directory
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct CliArgs {
-    /// Directory to serve
-    #[arg(long)]
-    dir: Option<String>,
-
-    /// Address to listen on
-    #[arg(long, default_value = "0.0.0.0")]
-    listen: String,
-
-    /// Port to listen on
-    #[arg(short, long, default_value = "3000")]
-    port: u16,
-}
+mod bricklink;
+mod cache;
+mod catalog;
+mod config;
+mod document;
+mod epub;
+mod image_type;
+mod metadata;
+mod optional;
+mod paths;
+mod release_date;
+mod scan;
+mod set_number;
+mod transcode;
+mod watch;
+
+use cache::IndexCache;
+use catalog::{Catalog, CatalogSet, InventoryPart};
+use config::Settings;
+use document::{Document, should_expose};
+use release_date::ReleaseDate;
+use scan::{Library, Problem, ScanProgress};
+use set_number::SetNumber;
+use transcode::{PageKey, Transcoder};
 
 type SharedState = Arc<RwLock<AppState>>;
 
 #[derive(Debug)]
 struct AppState {
     files: Vec<File>,
-    all_years: BTreeSet<String>,
+    all_years: BTreeSet<i32>,
     all_genres: BTreeSet<String>,
+    all_tags: BTreeSet<String>,
+    all_languages: BTreeSet<String>,
+    all_roots: Vec<String>,
+    default_sort: FileSort,
+    problems: Vec<Problem>,
+    scan: Arc<ScanProgress>,
+    cache_path: Option<PathBuf>,
+    transcoder: Option<Transcoder>,
 }
 
 impl AppState {
     fn from_files(files: Vec<File>) -> Self {
-        let all_years = files
+        let mut state = Self {
+            files,
+            all_years: BTreeSet::new(),
+            all_genres: BTreeSet::new(),
+            all_tags: BTreeSet::new(),
+            all_languages: BTreeSet::new(),
+            all_roots: vec![],
+            default_sort: FileSort::default(),
+            problems: vec![],
+            scan: Arc::default(),
+            cache_path: None,
+            transcoder: None,
+        };
+        state.update_indexes();
+        state
+    }
+
+    fn with_roots(self, all_roots: Vec<String>) -> Self {
+        Self { all_roots, ..self }
+    }
+
+    fn with_default_sort(self, default_sort: FileSort) -> Self {
+        Self {
+            default_sort,
+            ..self
+        }
+    }
+
+    fn with_cache_path(self, cache_path: Option<PathBuf>) -> Self {
+        Self { cache_path, ..self }
+    }
+
+    fn with_transcoder(self, transcoder: Option<Transcoder>) -> Self {
+        Self { transcoder, ..self }
+    }
+
+    fn save_cache(&self) {
+        if let Some(cache_path) = &self.cache_path
+            && let Err(e) = IndexCache::save(cache_path, &self.files)
+        {
+            eprintln!("failed to save cache {}: {:#}", cache_path.display(), e);
+        }
+    }
+
+    fn update_indexes(&mut self) {
+        self.all_years = self
+            .files
             .iter()
-            .filter(|f| !f.year().is_empty())
-            .map(|f| f.year().to_string())
+            .filter_map(|f| f.release_date())
+            .map(|d| d.year)
+            .collect::<BTreeSet<_>>();
+        self.all_genres = self
+            .files
+            .iter()
+            .flat_map(|f| f.genres().iter().chain(f.themes()))
+            .cloned()
             .collect::<BTreeSet<_>>();
-        let all_genres = files
+        self.all_tags = self
+            .files
             .iter()
-            .flat_map(|f| f.genres())
+            .flat_map(|f| f.tags())
             .cloned()
             .collect::<BTreeSet<_>>();
+        self.all_languages = self
+            .files
+            .iter()
+            .filter(|f| !f.language().is_empty())
+            .map(|f| f.language().to_string())
+            .collect::<BTreeSet<_>>();
+    }
 
-        Self {
-            files,
-            all_years,
-            all_genres,
+    /// The booklets of the set `file` belongs to, as in `group_booklets`
+    fn booklets(&self, file: &File) -> Vec<Vec<&File>> {
+        let number = file.set_number();
+        let files = self
+            .files
+            .iter()
+            .filter(|f| f.path == file.path || (!f.number().is_empty() && f.set_number() == number))
+            .collect();
+        group_booklets(files)
+    }
+
+    /// A file of the set `number` and the set's catalog entry, if the catalog
+    /// has its parts
+    fn find_parts(&self, number: &SetNumber) -> Option<(&File, &CatalogSet)> {
+        self.files
+            .iter()
+            .filter(|f| f.set_number() == *number)
+            .find_map(|f| Some((f, f.catalog.as_deref()?)))
+            .filter(|(_, set)| !set.inventory.is_empty())
+    }
+
+    /// The file whose URL path `path` starts with, and the rest of `path`
+    fn find_file<'a>(&self, path: &'a [u8]) -> Option<(&File, &'a [u8])> {
+        self.files.iter().find_map(|f| {
+            // the same as stripping `url_path()`, without building it
+            let rest = if f.root.is_empty() {
+                path
+            } else {
+                path.strip_prefix(f.root.as_bytes())?.strip_prefix(b"/")?
+            };
+            let subpath = rest.strip_prefix(f.relative_path.as_os_str().as_encoded_bytes())?;
+            (subpath.is_empty() || subpath.starts_with(b"/")).then_some((f, subpath))
+        })
+    }
+
+    /// Add a file, replacing any existing entry with the same path
+    fn upsert(&mut self, file: File) {
+        self.problems.retain(|p| p.path != file.path);
+        match self.files.iter_mut().find(|f| f.path == file.path) {
+            Some(existing) => *existing = file,
+            None => self.files.push(file),
         }
     }
+
+    /// Remove the file at `path`, or every file below it if it was a directory
+    fn remove(&mut self, path: &Path) -> usize {
+        self.problems.retain(|p| !p.path.starts_with(path));
+        let before = self.files.len();
+        self.files.retain(|f| !f.path.starts_with(path));
+        before - self.files.len()
+    }
+
+    /// Record a file that couldn't be loaded, dropping any stale entry for it
+    fn quarantine(&mut self, problem: Problem) {
+        self.files.retain(|f| f.path != problem.path);
+        self.problems.retain(|p| p.path != problem.path);
+        self.problems.push(problem);
+    }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 struct File {
     title: String,
-    relative_path: String,
+    /// Label of the library root the file was found in
+    root: String,
+    #[serde(with = "paths")]
+    relative_path: PathBuf,
+    #[serde(with = "paths")]
     path: PathBuf,
+    /// File name without the extension
+    file_name: String,
+    /// Revision from a `V39` or `rev2` in the file name
+    revision: Option<u32>,
+    /// The file name with the revision left out, in lower case
+    booklet_name: String,
     info: Option<ComicInfo>,
+    metadata_error: Option<String>,
     pages: usize,
     size: u64,
     modified: SystemTime,
+    #[serde(skip)]
+    catalog: Option<Arc<CatalogSet>>,
 }
 
+/// The ComicInfo.xml schema, version 2.1
 #[serde_as]
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default, rename_all = "PascalCase")]
 struct ComicInfo {
-    #[serde(rename = "Title")]
     title: String,
-    #[serde(rename = "Series")]
     series: String,
-    #[serde(rename = "Number")]
     number: String,
-    #[serde(rename = "Year")]
-    year: String,
-    #[serde(rename = "Publisher")]
+    #[serde(with = "optional")]
+    count: Option<i32>,
+    #[serde(with = "optional")]
+    volume: Option<i32>,
+    alternate_series: String,
+    alternate_number: String,
+    #[serde(with = "optional")]
+    alternate_count: Option<i32>,
+    summary: String,
+    notes: String,
+    #[serde(with = "optional")]
+    year: Option<i32>,
+    #[serde(with = "optional")]
+    month: Option<u32>,
+    #[serde(with = "optional")]
+    day: Option<u32>,
+    writer: String,
+    penciller: String,
+    inker: String,
+    colorist: String,
+    letterer: String,
+    cover_artist: String,
+    editor: String,
+    translator: String,
     publisher: String,
+    imprint: String,
     #[serde_as(as = "StringWithSeparator::<CommaSeparator, String>")]
-    #[serde(rename = "Genre")]
     genre: Vec<String>,
-    #[serde(rename = "Web")]
+    #[serde_as(as = "StringWithSeparator::<CommaSeparator, String>")]
+    tags: Vec<String>,
     web: String,
+    #[serde(with = "optional")]
+    page_count: Option<u32>,
+    #[serde(rename = "LanguageISO")]
+    language_iso: String,
+    format: String,
+    black_and_white: String,
+    manga: String,
+    #[serde_as(as = "StringWithSeparator::<CommaSeparator, String>")]
+    characters: Vec<String>,
+    #[serde_as(as = "StringWithSeparator::<CommaSeparator, String>")]
+    teams: Vec<String>,
+    #[serde_as(as = "StringWithSeparator::<CommaSeparator, String>")]
+    locations: Vec<String>,
+    scan_information: String,
+    story_arc: String,
+    story_arc_number: String,
+    series_group: String,
+    age_rating: String,
+    pages: PageList,
+    #[serde(with = "optional")]
+    community_rating: Option<f32>,
+    main_character_or_team: String,
+    review: String,
+    #[serde(rename = "GTIN")]
+    gtin: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+struct PageList {
+    #[serde(rename = "Page")]
+    page: Vec<PageInfo>,
+}
+
+/// A `<Page>` entry of ComicInfo.xml
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+struct PageInfo {
+    /// Index of the image among the document's pages in natural order
+    #[serde(rename = "@Image", with = "optional")]
+    image: Option<usize>,
+    #[serde(rename = "@Type")]
+    kind: String,
+    #[serde(rename = "@DoublePage", with = "optional")]
+    double_page: Option<bool>,
+    #[serde(rename = "@ImageSize", with = "optional")]
+    image_size: Option<u64>,
+    #[serde(rename = "@Key")]
+    key: String,
+    #[serde(rename = "@Bookmark")]
+    bookmark: String,
+    #[serde(rename = "@ImageWidth", with = "optional")]
+    image_width: Option<u32>,
+    #[serde(rename = "@ImageHeight", with = "optional")]
+    image_height: Option<u32>,
 }
+
 impl ComicInfo {
-    fn from_xmp(xmp: &XmpMeta) -> Result<Self> {
+    fn from_xmp(xmp: &XmpMeta) -> Self {
         let title = xmp
             .localized_text(xmp_ns::DC, "title", Some("en"), "x-default")
-            .context("missing dc:title")?
-            .0
-            .value;
+            .map(|t| t.0.value)
+            .unwrap_or_default();
         let number = xmp
             .property(xmp_ns::DC, "identifier")
-            .context("missing dc:identifier")?
-            .value;
+            .map(|n| n.value)
+            .unwrap_or_default();
         let date = xmp
             .property_array(xmp_ns::DC, "date")
             .next()
-            .context("missing dc:date")?
-            .value;
+            .and_then(|d| d.value.parse::<ReleaseDate>().ok());
         let subject = xmp
             .property_array(xmp_ns::DC, "subject")
             .map(|s| s.value)
             .collect();
+        let description = xmp
+            .localized_text(xmp_ns::DC, "description", Some("en"), "x-default")
+            .map(|t| t.0.value)
+            .unwrap_or_default();
+        let language = xmp
+            .property_array(xmp_ns::DC, "language")
+            .next()
+            .map(|l| l.value)
+            .unwrap_or_default();
 
-        Ok(Self {
+        Self {
             title,
             number,
-            year: date,
+            year: date.map(|d| d.year),
+            month: date.and_then(|d| d.month),
+            day: date.and_then(|d| d.day),
             genre: subject,
+            summary: description,
+            language_iso: language,
             ..Default::default()
-        })
+        }
     }
-}
 
-impl File {
-    fn from_path(path: PathBuf, dir: &Path) -> Result<Self> {
-        match path.extension().map(|e| e.to_str()).flatten() {
-            Some("cbz") => Self::from_cbz(path, dir),
-            Some("pdf") => Self::from_pdf(path, dir),
-            _ => Err(anyhow!("Unsupported file extension")),
-        }
-    }
-
-    fn from_cbz(path: PathBuf, dir: &Path) -> Result<Self> {
-        let relative_path = path.strip_prefix(dir)?.to_str().unwrap().into();
-        let file = fs::File::open(&path)?;
-        let metadata = file.metadata()?;
-
-        let mut zip = ZipArchive::new(file)?;
-        let pages = zip.file_names().filter(|f| should_expose(f)).count();
-        let (title, info) = match zip.by_name("ComicInfo.xml") {
-            Ok(info_xml) => {
-                let info: ComicInfo = quick_xml::de::from_reader(BufReader::new(info_xml))?;
-                // println!("{:?}", info);
-                (info.title.clone(), Some(info))
-            }
-            _ => {
-                let filename = path.file_stem().unwrap().to_str().unwrap().into();
-                (filename, None)
+    /// Fill empty fields from `fallback`
+    fn or(self, fallback: Self) -> Self {
+        fn is_empty(value: &serde_json::Value) -> bool {
+            match value {
+                serde_json::Value::Null => true,
+                serde_json::Value::String(s) => s.is_empty(),
+                serde_json::Value::Array(a) => a.is_empty(),
+                serde_json::Value::Object(o) => o.values().all(is_empty),
+                _ => false,
             }
+        }
+        // merge field by field through the serialized form, so new fields
+        // don't need to be listed here
+        let (Ok(serde_json::Value::Object(mut fields)), Ok(serde_json::Value::Object(fallback))) =
+            (serde_json::to_value(&self), serde_json::to_value(fallback))
+        else {
+            return self;
         };
+        for (name, value) in fallback {
+            if fields.get(&name).is_none_or(is_empty) {
+                fields.insert(name, value);
+            }
+        }
+        match serde_json::from_value(serde_json::Value::Object(fields)) {
+            Ok(merged) => merged,
+            Err(e) => {
+                eprintln!("failed to merge metadata: {}", e);
+                self
+            }
+        }
+    }
 
-        Ok(Self {
-            title,
-            relative_path,
-            path,
-            info,
-            pages,
-            size: metadata.len(),
-            modified: metadata.modified()?,
-        })
+    /// Put `pages` in the order declared by `<Pages>`, dropping deleted pages.
+    /// Pages that aren't declared follow in their original order.
+    fn order_pages(&self, pages: Vec<String>) -> Vec<String> {
+        if self.pages.page.is_empty() {
+            return pages;
+        }
+        let mut ordered = vec![];
+        let mut listed = vec![false; pages.len()];
+        for page in &self.pages.page {
+            let Some(index) = page.image.filter(|i| *i < pages.len()) else {
+                continue;
+            };
+            if !listed[index] && page.kind != "Deleted" {
+                ordered.push(pages[index].clone());
+            }
+            listed[index] = true;
+        }
+        let unlisted = pages.into_iter().zip(listed).filter(|(_, l)| !l);
+        ordered.extend(unlisted.map(|(page, _)| page));
+        ordered
     }
 
-    fn from_pdf(path: PathBuf, dir: &Path) -> Result<Self> {
-        let relative_path = path.strip_prefix(dir)?.to_str().unwrap().into();
-        let file = fs::File::open(&path)?;
-        let metadata = file.metadata()?;
+    /// Release date from Year, Month and Day, as precise as they allow
+    fn release_date(&self) -> Option<ReleaseDate> {
+        Some(ReleaseDate::new(self.year?, self.month, self.day))
+    }
 
-        // check for XMP sidecar
-        let xmp_path = path.with_extension("xmp");
-        let (title, info) = if xmp_path.exists() {
-            let xmp = XmpMeta::from_file(xmp_path)?;
-            let info = ComicInfo::from_xmp(&xmp)?;
-            (info.title.clone(), Some(info))
+    /// Describe required fields that are missing, if any
+    fn missing_fields(&self) -> Option<String> {
+        let missing = [("title", &self.title), ("number", &self.number)]
+            .into_iter()
+            .filter(|(_, value)| value.is_empty())
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            None
         } else {
-            let title = path.file_stem().unwrap().to_str().unwrap().into();
-            (title, None)
-        };
+            Some(format!("missing {}", missing.join(", ")))
+        }
+    }
+}
 
-        let pdf_document = pdf::file::FileOptions::cached().open(&path)?;
-        let pages = pdf_document.num_pages();
+impl File {
+    fn from_path(path: PathBuf, library: &Library) -> Result<Self> {
+        let (size, modified) = file_stats(&path)?;
+        let mut document = document::open(&path)?;
+
+        // a broken layer is reported, but doesn't hide the others
+        let mut errors = vec![];
+        let mut layers = vec![];
+        let sidecars = metadata::read_sidecars(&path).transpose();
+        for info in sidecars.into_iter().chain(document.comic_info_layers()) {
+            match info {
+                Ok(info) => layers.push(info),
+                Err(e) => errors.push(format!("{:#}", e)),
+            }
+        }
+        let info = library.apply_overrides(layers.into_iter().reduce(ComicInfo::or), &path);
+        let error = (!errors.is_empty()).then(|| errors.join("; "));
+        let pages = document.page_count(info.as_ref())?;
+
+        Self::new(path, library, size, modified, info, error, pages)
+    }
+
+    fn new(
+        path: PathBuf,
+        library: &Library,
+        size: u64,
+        modified: SystemTime,
+        info: Option<ComicInfo>,
+        error: Option<String>,
+        pages: usize,
+    ) -> Result<Self> {
+        let relative_path = path.strip_prefix(&library.dir)?.to_path_buf();
+        let metadata_error = error.or_else(|| info.as_ref().and_then(|i| i.missing_fields()));
+        let file_name = if path.is_dir() {
+            path.file_name()
+        } else {
+            path.file_stem()
+        };
+        let file_name = file_name.unwrap_or_default().to_string_lossy().into_owned();
+        let (booklet_name, revision) = split_revision(&file_name);
+        let title = match &info {
+            Some(info) if !info.title.is_empty() => info.title.clone(),
+            _ => file_name.clone(),
+        };
+        if let Some(e) = &metadata_error {
+            eprintln!("{}: {}", path.display(), e);
+        }
+        let number = info.as_ref().map_or("", |i| &i.number);
+        let catalog = library.catalog_set(&SetNumber::parse(number));
 
         Ok(Self {
             title,
+            root: library.label.clone(),
             relative_path,
             path,
+            file_name,
+            revision,
+            booklet_name,
             info,
-            pages: pages as usize,
-            size: metadata.len(),
-            modified: metadata.modified()?,
+            metadata_error,
+            pages,
+            size,
+            modified,
+            catalog,
         })
     }
 
-    fn is_pdf(&self) -> bool {
-        self.path.extension().is_some_and(|e| e == "pdf")
+    /// The title from the metadata, else the set's official name, else the
+    /// file name
+    fn title(&self) -> &str {
+        let has_title = self.info.as_ref().is_some_and(|i| !i.title.is_empty());
+        match &self.catalog {
+            Some(set) if !has_title => &set.name,
+            _ => &self.title,
+        }
     }
 
     fn name(&self) -> String {
-        format!("{} {}", self.number(), self.title)
+        format!("{} {}", self.number(), self.title())
     }
 
     fn number(&self) -> &str {
         self.info.as_ref().map_or("", |i| &i.number)
     }
 
+    fn set_number(&self) -> SetNumber {
+        SetNumber::parse(self.number())
+    }
+
+    fn volume(&self) -> Option<i32> {
+        self.info.as_ref().and_then(|i| i.volume)
+    }
+
+    /// Reading order of booklets within a set: by volume, then by name
+    fn booklet_cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.volume()
+            .cmp(&other.volume())
+            .then_with(|| self.root.cmp(&other.root))
+            .then_with(|| {
+                document::natural_cmp(
+                    &self.relative_path.to_string_lossy(),
+                    &other.relative_path.to_string_lossy(),
+                )
+            })
+    }
+
+    /// Revisions of a booklet share a volume and a file name once the
+    /// revision is left out
+    fn booklet_key(&self) -> (Option<i32>, &str) {
+        (self.volume(), &self.booklet_name)
+    }
+
+    /// How to label this file among the revisions of its booklet
+    fn revision_label(&self) -> String {
+        match self.revision {
+            Some(revision) => format!("V{}", revision),
+            None => self.file_name.clone(),
+        }
+    }
+
     fn genres(&self) -> &[String] {
         match &self.info {
             Some(info) => &info.genre,
@@ -227,12 +566,93 @@ impl File {
         }
     }
 
-    fn year(&self) -> &str {
-        self.info.as_ref().map_or("", |i| &i.year)
+    fn catalog_themes(&self) -> Option<&[String]> {
+        let themes = self.catalog.as_ref().map(|s| s.themes.as_slice());
+        themes.filter(|t| !t.is_empty())
+    }
+
+    /// The catalog's theme hierarchy if the set is in the catalog, otherwise
+    /// the genres
+    fn themes(&self) -> &[String] {
+        self.catalog_themes().unwrap_or(self.genres())
+    }
+
+    /// How to join `themes()`
+    fn theme_separator(&self) -> &str {
+        if self.catalog_themes().is_some() {
+            "›"
+        } else {
+            "/"
+        }
+    }
+
+    fn parts(&self) -> Option<u32> {
+        self.catalog.as_ref().map(|s| s.parts)
+    }
+
+    fn tags(&self) -> &[String] {
+        match &self.info {
+            Some(info) => &info.tags,
+            None => <&[String]>::default(),
+        }
+    }
+
+    fn language(&self) -> &str {
+        self.info.as_ref().map_or("", |i| &i.language_iso)
+    }
+
+    fn summary(&self) -> &str {
+        self.info.as_ref().map_or("", |i| &i.summary)
+    }
+
+    fn release_date(&self) -> Option<ReleaseDate> {
+        self.info.as_ref().and_then(|i| i.release_date())
+    }
+
+    /// Permanent link to the set, which survives the file being renamed
+    fn set_url(&self) -> String {
+        format!("/set/{}", encode_path_segment(&self.set_number().slug()))
+    }
+
+    fn compare_url(&self, with: usize, page: usize) -> String {
+        format!(
+            "/compare/{}?with={}&page={}",
+            self.encoded_path(),
+            with,
+            page
+        )
+    }
+
+    /// URL of the image data of `page`
+    fn raw_page_url(&self, page: &str) -> String {
+        format!(
+            "/view/{}/{}?raw&v={}",
+            self.encoded_path(),
+            encode_path_segment(page),
+            self.version(),
+        )
     }
 
     fn view_url(&self) -> String {
-        format!("/view/{}", encode_path_segment(self.relative_path.as_str()),)
+        format!("/view/{}", self.encoded_path())
+    }
+
+    /// Path of the file in URLs, prefixed with its root's label
+    fn url_path(&self) -> Vec<u8> {
+        let relative_path = self.relative_path.as_os_str().as_encoded_bytes();
+        if self.root.is_empty() {
+            relative_path.to_vec()
+        } else {
+            [self.root.as_bytes(), b"/", relative_path].concat()
+        }
+    }
+
+    fn encoded_path(&self) -> String {
+        percent_encode(&self.url_path(), NON_ALPHANUMERIC).to_string()
+    }
+
+    fn display_path(&self) -> String {
+        String::from_utf8_lossy(&self.url_path()).into_owned()
     }
 
     fn version(&self) -> String {
@@ -249,31 +669,165 @@ fn genre_search_url(genre: &str) -> String {
         .to_url()
 }
 
-fn year_search_url(year: &str) -> String {
-    IndexQuery::default()
-        .with_year_filter(Some(year.to_string()))
-        .to_url()
+/// Split a revision like `V39` or `rev2` out of a file name, leaving the rest
+/// in lower case. Only the last word or one after a `_` or `-` counts, so
+/// the `V8` of `V8 Engine` isn't taken for a revision.
+fn split_revision(name: &str) -> (String, Option<u32>) {
+    // each word with the separator before it
+    let mut words = vec![];
+    let mut separator = None;
+    let mut start = None;
+    for (i, c) in name.char_indices() {
+        if c.is_alphanumeric() {
+            start.get_or_insert(i);
+        } else {
+            if let Some(start) = start.take() {
+                words.push((separator, &name[start..i]));
+            }
+            separator = Some(c);
+        }
+    }
+    if let Some(start) = start {
+        words.push((separator, &name[start..]));
+    }
+
+    let mut revision = None;
+    let mut rest = vec![];
+    let last = words.len().saturating_sub(1);
+    for (i, (separator, word)) in words.into_iter().enumerate() {
+        let lower = word.to_lowercase();
+        let number = lower
+            .strip_prefix("rev")
+            .or_else(|| lower.strip_prefix('v'))
+            .filter(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+            .filter(|_| i == last || matches!(separator, Some('_' | '-')));
+        match number.and_then(|n| n.parse().ok()) {
+            Some(number) if revision.is_none() => revision = Some(number),
+            _ => rest.push(lower),
+        }
+    }
+    (rest.join(" "), revision)
 }
 
-fn find_files(dir: &Path) -> Result<Vec<PathBuf>, io::Error> {
-    fn collect_files(parent: &Path, results: &mut Vec<PathBuf>) -> Result<(), io::Error> {
-        for entry in fs::read_dir(parent)? {
-            let path = entry?.path();
-            if path.is_dir() {
-                collect_files(&path, results)?
-            } else if path
-                .extension()
-                .is_some_and(|ext| ext == "cbz" || ext == "pdf")
-            {
-                results.push(path)
+/// Group the files of one set into booklets in reading order, each a list of
+/// its revisions with the preferred one, the newest, first
+fn group_booklets(files: Vec<&File>) -> Vec<Vec<&File>> {
+    let mut booklets: Vec<Vec<&File>> = vec![];
+    for file in files {
+        let key = file.booklet_key();
+        match booklets.iter_mut().find(|b| b[0].booklet_key() == key) {
+            Some(revisions) => revisions.push(file),
+            None => booklets.push(vec![file]),
+        }
+    }
+    for revisions in &mut booklets {
+        revisions.sort_by_key(|f| std::cmp::Reverse((f.revision, f.modified)));
+    }
+    booklets.sort_by(|a, b| a[0].booklet_cmp(b[0]));
+    booklets
+}
+
+/// The booklets of one set, in reading order
+struct Set<'a> {
+    booklets: Vec<Vec<&'a File>>,
+}
+
+impl<'a> Set<'a> {
+    /// Group files that share a set number, keeping the order in which each
+    /// set first appears. Files without a number are sets of their own.
+    fn group(files: impl IntoIterator<Item = &'a File>) -> Vec<Self> {
+        let mut sets: Vec<Vec<&File>> = vec![];
+        let mut indexes: HashMap<SetNumber, usize> = HashMap::new();
+        for file in files {
+            if file.number().is_empty() {
+                sets.push(vec![file]);
+                continue;
+            }
+            match indexes.entry(file.set_number()) {
+                Entry::Occupied(e) => sets[*e.get()].push(file),
+                Entry::Vacant(e) => {
+                    e.insert(sets.len());
+                    sets.push(vec![file]);
+                }
             }
         }
-        Ok(())
+        sets.into_iter()
+            .map(|files| Self {
+                booklets: group_booklets(files),
+            })
+            .collect()
+    }
+
+    fn first(&self) -> &'a File {
+        self.booklets[0][0]
+    }
+
+    /// The preferred revision of each booklet
+    fn preferred(&self) -> impl Iterator<Item = &'a File> {
+        self.booklets.iter().map(|revisions| revisions[0])
+    }
+
+    fn pages(&self) -> usize {
+        self.preferred().map(|f| f.pages).sum()
+    }
+
+    fn size(&self) -> u64 {
+        self.preferred().map(|f| f.size).sum()
+    }
+}
+
+/// Where a booklet comes in its set
+struct Booklet<'a> {
+    index: usize,
+    count: usize,
+    previous: Option<&'a File>,
+    next: Option<&'a File>,
+    /// Revisions of the booklet, preferred first
+    revisions: Vec<&'a File>,
+}
+
+impl<'a> Booklet<'a> {
+    fn new(file: &File, booklets: &[Vec<&'a File>]) -> Self {
+        let index = booklets
+            .iter()
+            .position(|b| b.iter().any(|f| f.path == file.path))
+            .unwrap_or_default();
+        Self {
+            index,
+            count: booklets.len(),
+            previous: index.checked_sub(1).map(|i| booklets[i][0]),
+            next: booklets.get(index + 1).map(|b| b[0]),
+            revisions: booklets.get(index).cloned().unwrap_or_default(),
+        }
+    }
+
+    /// Revisions of the booklet other than `file`
+    fn others(&self, file: &File) -> Vec<&'a File> {
+        let others = self.revisions.iter().filter(|f| f.path != file.path);
+        others.copied().collect()
     }
+}
+
+fn root_search_url(root: &str) -> String {
+    IndexQuery::default()
+        .with_root_filter(Some(root.to_string()))
+        .to_url()
+}
+
+fn year_search_url(year: i32) -> String {
+    IndexQuery::default().with_year_filter(Some(year)).to_url()
+}
+
+fn tag_search_url(tag: &str) -> String {
+    IndexQuery::default()
+        .with_tag_filter(Some(tag.to_string()))
+        .to_url()
+}
 
-    let mut results: Vec<PathBuf> = vec![];
-    collect_files(dir, &mut results)?;
-    Ok(results)
+fn language_search_url(language: &str) -> String {
+    IndexQuery::default()
+        .with_language_filter(Some(language.to_string()))
+        .to_url()
 }
 
 fn format_bytes(value: u64) -> String {
@@ -289,16 +843,41 @@ fn format_bytes(value: u64) -> String {
 #[derive(TemplateSimple)]
 #[template(path = "index.stpl")]
 struct IndexTemplate<'a> {
-    files: Vec<&'a File>,
+    sets: Vec<Set<'a>>,
     query: IndexQuery,
-    all_years: &'a BTreeSet<String>,
+    all_years: &'a BTreeSet<i32>,
     all_genres: &'a BTreeSet<String>,
+    all_tags: &'a BTreeSet<String>,
+    all_languages: &'a BTreeSet<String>,
+    all_roots: &'a [String],
+    /// Whether any file has a piece count to filter by
+    has_parts: bool,
+    scanning: Option<(usize, usize)>,
+}
+
+#[derive(TemplateSimple)]
+#[template(path = "admin.stpl")]
+struct AdminTemplate<'a> {
+    problems: &'a [Problem],
+    incomplete: Vec<&'a File>,
+}
+
+#[derive(TemplateSimple)]
+#[template(path = "parts.stpl")]
+struct PartsTemplate<'a> {
+    file: &'a File,
+    inventory: &'a [InventoryPart],
+    /// Parts left out of the wanted list, since their colors have no known
+    /// BrickLink id
+    unlisted: Vec<&'a InventoryPart>,
 }
 
 #[derive(TemplateSimple)]
 #[template(path = "view.stpl")]
 struct ViewTemplate<'a> {
     file: &'a File,
+    booklet: Booklet<'a>,
+    page_index: usize,
     image_url: String,
     next_url: Option<String>,
     previous_url: Option<String>,
@@ -308,49 +887,126 @@ struct ViewTemplate<'a> {
 #[template(path = "pdf_view.stpl")]
 struct PdfViewTemplate<'a> {
     file: &'a File,
+    booklet: Booklet<'a>,
     image_url: String,
 }
 
+/// One side of a comparison: a page image, or a whole document if the file
+/// isn't paged
+struct CompareSide<'a> {
+    file: &'a File,
+    url: Option<String>,
+    whole: bool,
+}
+
+#[derive(TemplateSimple)]
+#[template(path = "compare.stpl")]
+struct CompareTemplate<'a> {
+    file: &'a File,
+    booklet: Booklet<'a>,
+    sides: Vec<CompareSide<'a>>,
+    page_index: usize,
+    page_count: usize,
+    with: usize,
+}
+
 #[tokio::main]
-async fn main() {
-    let args = CliArgs::parse();
-    let dir = match args.dir {
-        None => env::current_dir().unwrap(),
-        Some(path) => path.into(),
+async fn main() -> Result<()> {
+    let settings = Settings::load()?;
+
+    let catalog = match &settings.catalog {
+        Some(dir) => Arc::new(Catalog::load(dir)?),
+        None => Arc::default(),
     };
-    let entries = find_files(&dir).unwrap();
-    println!("found {} files", entries.len());
-    let files = entries
+    let libraries = settings
+        .roots
         .into_iter()
-        .map(|e| File::from_path(e, &dir))
-        .collect::<Result<Vec<_>>>()
-        .unwrap();
+        .map(|(label, dir)| {
+            Library::new(
+                label,
+                dir,
+                &settings.ignore_globs,
+                settings.default_ignores,
+                settings.follow_symlinks,
+            )
+            .map(|l| Arc::new(l.with_catalog(catalog.clone())))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let transcoder = settings
+        .transcode
+        .then(|| Transcoder::new(settings.page_cache));
+    if let Some(transcoder) = transcoder.clone() {
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = transcoder.prune() {
+                eprintln!("failed to prune converted pages: {}", e);
+            }
+        });
+    }
 
-    let shared_state: SharedState = Arc::new(RwLock::new(AppState::from_files(files)));
+    let state = AppState::from_files(vec![])
+        .with_roots(libraries.iter().map(|l| l.label.clone()).collect())
+        .with_default_sort(settings.default_sort)
+        .with_cache_path(settings.cache_path)
+        .with_transcoder(transcoder);
+    let shared_state: SharedState = Arc::new(RwLock::new(state));
+    tokio::spawn(scan::scan_library(libraries.clone(), shared_state.clone()));
+    let _watcher = watch::watch_files(libraries, shared_state.clone())?;
 
     let app = Router::new()
         .route("/", get(show_index))
         .route("/view/{*path}", get(show_file))
+        .route("/set/{number}", get(show_set))
+        .route("/set/{number}/parts", get(show_parts))
+        .route("/set/{number}/parts.xml", get(show_wanted_list))
+        .route("/compare/{*path}", get(show_compare))
+        .route("/admin", get(show_admin))
+        .route("/status", get(show_status))
         .nest_service("/assets", ServeDir::new("assets"))
         .with_state(shared_state);
 
-    let sock_addr = SocketAddr::from((IpAddr::from_str(args.listen.as_str()).unwrap(), args.port));
-    println!("listening on http://{}", sock_addr);
-    let listener = TcpListener::bind(sock_addr).await.unwrap();
-    axum::serve(listener, app.into_make_service())
-        .await
-        .unwrap()
+    let mut servers = JoinSet::new();
+    for sock_addr in settings.listen {
+        let listener = TcpListener::bind(sock_addr)
+            .await
+            .with_context(|| format!("failed to listen on {}", sock_addr))?;
+        println!("listening on http://{}", sock_addr);
+        let app = app.clone();
+        servers.spawn(async move { axum::serve(listener, app.into_make_service()).await });
+    }
+    while let Some(result) = servers.join_next().await {
+        result??;
+    }
+    Ok(())
 }
 
 #[serde_as]
 #[derive(Clone, Deserialize, Default)]
 struct IndexQuery {
+    #[serde_as(as = "NoneAsEmptyString")]
+    #[serde(default)]
+    root: Option<String>,
     #[serde_as(as = "NoneAsEmptyString")]
     #[serde(default)]
     genre: Option<String>,
     #[serde_as(as = "NoneAsEmptyString")]
     #[serde(default)]
-    year: Option<String>,
+    year: Option<i32>,
+    #[serde_as(as = "NoneAsEmptyString")]
+    #[serde(default)]
+    tag: Option<String>,
+    #[serde_as(as = "NoneAsEmptyString")]
+    #[serde(default)]
+    language: Option<String>,
+    #[serde_as(as = "NoneAsEmptyString")]
+    #[serde(default)]
+    set: Option<String>,
+    #[serde_as(as = "NoneAsEmptyString")]
+    #[serde(default)]
+    min_pieces: Option<u32>,
+    #[serde_as(as = "NoneAsEmptyString")]
+    #[serde(default)]
+    max_pieces: Option<u32>,
     #[serde_as(as = "NoneAsEmptyString")]
     #[serde(default)]
     sort: Option<FileSort>,
@@ -361,23 +1017,51 @@ impl IndexQuery {
         Self { sort, ..self }
     }
 
+    fn with_root_filter(self, root: Option<String>) -> Self {
+        Self { root, ..self }
+    }
+
     fn with_genre_filter(self, genre: Option<String>) -> Self {
         Self { genre, ..self }
     }
 
-    fn with_year_filter(self, year: Option<String>) -> Self {
+    fn with_year_filter(self, year: Option<i32>) -> Self {
         Self { year, ..self }
     }
 
+    fn with_tag_filter(self, tag: Option<String>) -> Self {
+        Self { tag, ..self }
+    }
+
+    fn with_language_filter(self, language: Option<String>) -> Self {
+        Self { language, ..self }
+    }
+
     fn to_url(&self) -> String {
         let base = "/?";
         let mut query = form_urlencoded::Serializer::for_suffix(String::from(base), base.len());
+        self.root
+            .as_ref()
+            .map(|r| query.append_pair("root", r.as_str()));
         self.genre
             .as_ref()
             .map(|g| query.append_pair("genre", g.as_str()));
         self.year
             .as_ref()
-            .map(|y| query.append_pair("year", y.as_str()));
+            .map(|y| query.append_pair("year", &y.to_string()));
+        self.tag
+            .as_ref()
+            .map(|t| query.append_pair("tag", t.as_str()));
+        self.language
+            .as_ref()
+            .map(|l| query.append_pair("language", l.as_str()));
+        self.set
+            .as_ref()
+            .map(|s| query.append_pair("set", s.as_str()));
+        self.min_pieces
+            .map(|p| query.append_pair("min_pieces", &p.to_string()));
+        self.max_pieces
+            .map(|p| query.append_pair("max_pieces", &p.to_string()));
         self.sort
             .map(|s| query.append_pair("sort", s.to_query().as_str()));
         query.finish()
@@ -429,6 +1113,7 @@ enum FileField {
     Name,
     Year,
     Genre,
+    Parts,
     Pages,
     Size,
 }
@@ -439,8 +1124,17 @@ struct FileSort {
     field: FileField,
 }
 
+impl Default for FileSort {
+    fn default() -> Self {
+        FileSort {
+            direction: Direction::Ascending,
+            field: FileField::Number,
+        }
+    }
+}
+
 impl FileSort {
-    fn to_query(&self) -> String {
+    fn to_query(self) -> String {
         match self.direction {
             Direction::Ascending => self.field.to_string(),
             Direction::Descending => format!("-{}", self.field),
@@ -471,6 +1165,7 @@ impl Display for FileField {
                 FileField::Name => "name",
                 FileField::Year => "year",
                 FileField::Genre => "genre",
+                FileField::Parts => "parts",
                 FileField::Pages => "pages",
                 FileField::Size => "size",
             }
@@ -487,6 +1182,7 @@ impl FromStr for FileField {
             "name" => Ok(FileField::Name),
             "year" => Ok(FileField::Year),
             "genre" => Ok(FileField::Genre),
+            "parts" => Ok(FileField::Parts),
             "pages" => Ok(FileField::Pages),
             "size" => Ok(FileField::Size),
             _ => Err(format!("Invalid FileField '{}'", s)),
@@ -499,52 +1195,169 @@ async fn show_index(
     Query(query): Query<IndexQuery>,
 ) -> Result<Html<String>, InternalError> {
     let state = state.read().await;
-    let mut files = state
+    let set = query.set.as_deref().map(SetNumber::parse);
+    let matching = state
         .files
         .iter()
+        .filter(|f| match &query.root {
+            Some(root) => &f.root == root,
+            _ => true,
+        })
         .filter(|f| match &query.genre {
-            Some(genre) => f.info.as_ref().is_some_and(|i| i.genre.contains(genre)),
+            Some(genre) => f.genres().contains(genre) || f.themes().contains(genre),
             _ => true,
         })
         .filter(|f| match &query.year {
-            Some(year) => f.info.as_ref().is_some_and(|i| &i.year == year),
+            Some(year) => f.release_date().is_some_and(|d| d.year == *year),
             _ => true,
         })
-        .collect::<Vec<_>>();
-
-    let sort = query.sort.unwrap_or(FileSort {
-        direction: Direction::Ascending,
-        field: FileField::Number,
+        .filter(|f| match &query.tag {
+            Some(tag) => f.tags().contains(tag),
+            _ => true,
+        })
+        .filter(|f| match &query.language {
+            Some(language) => f.language() == language,
+            _ => true,
+        })
+        .filter(|f| match &set {
+            Some(set) => &f.set_number() == set,
+            _ => true,
+        })
+        .filter(|f| match (query.min_pieces, query.max_pieces) {
+            (None, None) => true,
+            (min, max) => f
+                .parts()
+                .is_some_and(|p| min.is_none_or(|m| p >= m) && max.is_none_or(|m| p <= m)),
+        })
+        .map(|f| f.path.as_path())
+        .collect::<HashSet<_>>();
+    // a set is listed whole if any of its files match
+    let mut sets = Set::group(&state.files);
+    sets.retain(|s| {
+        s.booklets
+            .iter()
+            .flatten()
+            .any(|f| matching.contains(f.path.as_path()))
     });
+
+    let sort = query.sort.unwrap_or(state.default_sort);
     match sort.field {
-        FileField::Number => files.sort_by_key(|f| split_name(&f.number())),
-        FileField::Name => files.sort_by_key(|f| f.title.to_ascii_lowercase()),
-        FileField::Year => files.sort_by_key(|f| f.year()),
-        FileField::Genre => files.sort_by_key(|f| f.genres()),
-        FileField::Pages => files.sort_by_key(|f| f.pages),
-        FileField::Size => files.sort_by_key(|f| f.size),
+        FileField::Number => sets.sort_by_cached_key(|s| s.first().set_number()),
+        FileField::Name => sets.sort_by_key(|s| s.first().title().to_ascii_lowercase()),
+        FileField::Year => sets.sort_by_key(|s| s.first().release_date()),
+        FileField::Genre => sets.sort_by_key(|s| s.first().themes()),
+        FileField::Parts => sets.sort_by_key(|s| s.first().parts()),
+        FileField::Pages => sets.sort_by_key(|s| s.pages()),
+        FileField::Size => sets.sort_by_key(|s| s.size()),
     }
     if sort.direction == Direction::Descending {
-        files.reverse()
+        sets.reverse()
     }
 
     let ctx = IndexTemplate {
-        files,
+        sets,
         query,
         all_years: &state.all_years,
         all_genres: &state.all_genres,
+        all_tags: &state.all_tags,
+        all_languages: &state.all_languages,
+        all_roots: &state.all_roots,
+        has_parts: state.files.iter().any(|f| f.parts().is_some()),
+        scanning: (!state.scan.is_finished()).then(|| state.scan.counts()),
     };
     Ok(Html(ctx.render_once()?))
 }
 
-fn split_name(name: &str) -> (u32, &str) {
-    if let Some(first_nonnumber) = name.find(|ch: char| !ch.is_ascii_digit()) {
-        let (num, rest) = name.split_at(first_nonnumber);
-        if let Ok(num) = num.parse() {
-            return (num, rest);
-        }
+async fn show_admin(State(state): State<SharedState>) -> Result<Html<String>, InternalError> {
+    let state = state.read().await;
+    let mut incomplete = state
+        .files
+        .iter()
+        .filter(|f| f.metadata_error.is_some())
+        .collect::<Vec<_>>();
+    incomplete.sort_by_key(|f| &f.relative_path);
+
+    let ctx = AdminTemplate {
+        problems: &state.problems,
+        incomplete,
+    };
+    Ok(Html(ctx.render_once()?))
+}
+
+#[derive(Serialize)]
+struct Status {
+    scanning: bool,
+    scanned: usize,
+    total: usize,
+    files: usize,
+    problems: usize,
+}
+
+/// Find a set by number, starting at its first booklet
+async fn show_set(
+    State(state): State<SharedState>,
+    extract::Path(number): extract::Path<String>,
+) -> Response {
+    let state = state.read().await;
+    let number = SetNumber::parse(&number);
+    match state.files.iter().find(|f| f.set_number() == number) {
+        Some(file) => Redirect::to(&state.booklets(file)[0][0].view_url()).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
     }
-    (u32::MAX, name)
+}
+
+/// The parts of a set from the catalog
+async fn show_parts(
+    State(state): State<SharedState>,
+    extract::Path(number): extract::Path<String>,
+) -> Result<Response, InternalError> {
+    let state = state.read().await;
+    let Some((file, set)) = state.find_parts(&SetNumber::parse(&number)) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let ctx = PartsTemplate {
+        file: state.booklets(file)[0][0],
+        inventory: &set.inventory,
+        unlisted: set
+            .inventory
+            .iter()
+            .filter(|l| !l.spare && bricklink::color_id(&l.color).is_none())
+            .collect(),
+    };
+    Ok(Html(ctx.render_once()?).into_response())
+}
+
+/// The parts of a set from the catalog as a BrickLink wanted list
+async fn show_wanted_list(
+    State(state): State<SharedState>,
+    extract::Path(number): extract::Path<String>,
+) -> Response {
+    let state = state.read().await;
+    let number = SetNumber::parse(&number);
+    let Some((_, set)) = state.find_parts(&number) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let disposition = format!("attachment; filename=\"{}-parts.xml\"", number.slug());
+    (
+        [
+            (header::CONTENT_TYPE, "application/xml"),
+            (header::CONTENT_DISPOSITION, disposition.as_str()),
+        ],
+        bricklink::wanted_list(&set.inventory),
+    )
+        .into_response()
+}
+
+async fn show_status(State(state): State<SharedState>) -> Json<Status> {
+    let state = state.read().await;
+    let (scanned, total) = state.scan.counts();
+    Json(Status {
+        scanning: !state.scan.is_finished(),
+        scanned,
+        total,
+        files: state.files.len(),
+        problems: state.problems.len(),
+    })
 }
 
 #[cfg(test)]
@@ -552,11 +1365,132 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_split_name() {
-        assert_eq!(split_name("123 Hello"), (123, " Hello"));
-        assert_eq!(split_name("Hello"), (u32::MAX, "Hello"));
-        assert_eq!(split_name("Hello 123"), (u32::MAX, "Hello 123"));
+    fn test_comic_info_or() {
+        let overrides = ComicInfo {
+            title: "Lion Knights' Castle".to_string(),
+            month: Some(8),
+            ..ComicInfo::default()
+        };
+        let sidecar = ComicInfo {
+            title: "Castle".to_string(),
+            number: "10305".to_string(),
+            year: Some(2022),
+            month: Some(7),
+            genre: vec!["Icons".to_string()],
+            community_rating: Some(4.5),
+            ..ComicInfo::default()
+        };
+        let document = ComicInfo {
+            number: "10305-1".to_string(),
+            summary: "Build a castle".to_string(),
+            genre: vec!["Castle".to_string()],
+            pages: PageList {
+                page: vec![PageInfo::default()],
+            },
+            ..ComicInfo::default()
+        };
+        let info = [overrides, sidecar, document]
+            .into_iter()
+            .reduce(ComicInfo::or)
+            .unwrap();
+        assert_eq!(info.title, "Lion Knights' Castle");
+        assert_eq!(info.number, "10305");
+        assert_eq!((info.year, info.month), (Some(2022), Some(8)));
+        assert_eq!(info.genre, ["Icons"]);
+        assert_eq!(info.community_rating, Some(4.5));
+        assert_eq!(info.summary, "Build a castle");
+        assert_eq!(info.pages.page.len(), 1);
+    }
+
+    #[test]
+    fn test_split_revision() {
+        assert_eq!(
+            split_revision("10305 Book1 V39"),
+            ("10305 book1".to_string(), Some(39))
+        );
+        assert_eq!(
+            split_revision("6186943_rev2"),
+            ("6186943".to_string(), Some(2))
+        );
+        assert_eq!(
+            split_revision("6080 Vault"),
+            ("6080 vault".to_string(), None)
+        );
+        assert_eq!(
+            split_revision("42111 V8 Engine"),
+            ("42111 v8 engine".to_string(), None)
+        );
+        assert_eq!(
+            split_revision("42111 V8 Engine-v2"),
+            ("42111 v8 engine".to_string(), Some(2))
+        );
+    }
+}
+
+#[derive(Deserialize)]
+struct CompareQuery {
+    /// Which of the other revisions to compare with
+    #[serde(default)]
+    with: usize,
+    #[serde(default)]
+    page: usize,
+}
+
+/// Show a page of one revision of a booklet next to the same page of another
+async fn show_compare(
+    State(state): State<SharedState>,
+    uri: Uri,
+    Query(query): Query<CompareQuery>,
+) -> Result<Response, InternalError> {
+    let state = state.read().await;
+    let path = uri.path().strip_prefix("/compare/").unwrap_or_default();
+    let path = percent_decode_str(path).collect::<Vec<_>>();
+    let Some((file, [])) = state.find_file(&path) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let booklets = state.booklets(file);
+    let booklet = Booklet::new(file, &booklets);
+    let Some(other) = booklet.others(file).get(query.with).copied() else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    // pages are matched by position
+    let mut pages = vec![];
+    for f in [file, other] {
+        let mut document = document::open(&f.path)?;
+        pages.push(match document.whole_content_type() {
+            Some(_) => None,
+            None => Some(document.pages(f.info.as_ref())?),
+        });
     }
+    let page_count = pages.iter().flatten().map(|p| p.len()).max().unwrap_or(1);
+    let page_index = query.page.min(page_count.saturating_sub(1));
+    let sides = [file, other]
+        .into_iter()
+        .zip(pages)
+        .map(|(f, pages)| match pages {
+            Some(pages) => CompareSide {
+                file: f,
+                url: pages.get(page_index).map(|p| f.raw_page_url(p)),
+                whole: false,
+            },
+            None => CompareSide {
+                file: f,
+                url: Some(format!("/view/{}?raw&v={}", f.encoded_path(), f.version())),
+                whole: true,
+            },
+        })
+        .collect();
+
+    let ctx = CompareTemplate {
+        file,
+        booklet,
+        sides,
+        page_index,
+        page_count,
+        with: query.with,
+    };
+    Ok(Html(ctx.render_once()?).into_response())
 }
 
 #[derive(Deserialize)]
@@ -564,80 +1498,166 @@ struct ShowFileQuery {
     raw: Option<String>,
 }
 
-fn should_expose(filename: &str) -> bool {
-    filename.ends_with(".jpg") || filename.ends_with(".gif")
+/// Size and modification time of a file, or for a folder of images the total
+/// size and latest modification of its contents. Changes to sidecars count
+/// as modifications.
+fn file_stats(path: &Path) -> io::Result<(u64, SystemTime)> {
+    let metadata = fs::metadata(path)?;
+    let mut size = 0;
+    let mut modified = metadata.modified()?;
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let metadata = entry?.metadata()?;
+            size += metadata.len();
+            modified = modified.max(metadata.modified()?);
+        }
+    } else {
+        size = metadata.len();
+    }
+    if let Some(sidecars_modified) = metadata::sidecars_modified(path) {
+        modified = modified.max(sidecars_modified);
+    }
+    Ok((size, modified))
 }
 
 #[debug_handler]
 async fn show_file(
     State(state): State<SharedState>,
-    axum::extract::Path(path): axum::extract::Path<String>,
+    uri: Uri,
+    headers: HeaderMap,
     Query(query): Query<ShowFileQuery>,
 ) -> Result<Response, InternalError> {
-    let state = state.read().await;
+    let conversion = {
+        let state = state.read().await;
 
-    let file = state
-        .files
-        .iter()
-        .find(|&f| path.starts_with(&f.relative_path));
-    if file.is_none() {
-        return Ok(StatusCode::NOT_FOUND.into_response());
+        // decode the path ourselves, since file names aren't necessarily UTF-8
+        let path = uri.path().strip_prefix("/view/").unwrap_or_default();
+        let path = percent_decode_str(path).collect::<Vec<_>>();
+        let Some((file, subpath)) = state.find_file(&path) else {
+            return Ok(StatusCode::NOT_FOUND.into_response());
+        };
+        let Ok(subpath) = std::str::from_utf8(subpath) else {
+            return Ok(StatusCode::NOT_FOUND.into_response());
+        };
+
+        let booklets = state.booklets(file);
+        let booklet = Booklet::new(file, &booklets);
+        let mut document = document::open(&file.path)?;
+        if let Some(content_type) = document.whole_content_type() {
+            return show_whole(file, booklet, content_type, subpath, query).await;
+        }
+        let transcode = state.transcoder.as_ref().map(|t| {
+            let accept = headers.get(header::ACCEPT).and_then(|a| a.to_str().ok());
+            (t, accept.unwrap_or_default())
+        });
+        match show_pages(file, booklet, document.as_mut(), subpath, query, transcode)? {
+            Shown::Done(response) => return Ok(response),
+            Shown::Convert(conversion) => conversion,
+        }
+    };
+    // converting is slow, so it's done off the runtime and without holding
+    // the state, which would hold up the watcher and every request behind it
+    Ok(tokio::task::spawn_blocking(move || conversion.run()).await?)
+}
+
+/// What `show_pages` produced: a response, or a page to convert first
+enum Shown {
+    Done(Response),
+    Convert(Conversion),
+}
+
+impl From<Response> for Shown {
+    fn from(response: Response) -> Self {
+        Self::Done(response)
     }
-    let file = file.unwrap();
-    if file.is_pdf() {
-        println!("is pdf");
-        show_pdf(file, path, query).await
-    } else {
-        println!("is cbz");
-        show_cbz(file, path, query)
+}
+
+/// A page to convert before sending it
+struct Conversion {
+    transcoder: Transcoder,
+    key: PageKey,
+    data: Vec<u8>,
+    content_type: &'static str,
+    accept: String,
+    modified: SystemTime,
+}
+
+impl Conversion {
+    fn run(self) -> Response {
+        let converted =
+            self.transcoder
+                .transcode(&self.key, &self.data, self.content_type, &self.accept);
+        let (data, content_type) = match converted {
+            Ok(Some(converted)) => converted,
+            Ok(None) => (self.data, self.content_type),
+            Err(e) => {
+                eprintln!("failed to convert {}: {:#}", self.key.page, e);
+                (self.data, self.content_type)
+            }
+        };
+        page_response(data, content_type, self.modified)
     }
 }
 
-fn show_cbz(file: &File, path: String, query: ShowFileQuery) -> Result<Response, InternalError> {
-    let mut zip = ZipArchive::new(fs::File::open(&file.path)?)?;
+fn page_response(data: Vec<u8>, content_type: &str, modified: SystemTime) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CACHE_CONTROL, "public, max-age=31536000"),
+            (header::LAST_MODIFIED, &fmt_http_date(modified)),
+            (header::VARY, "Accept"),
+        ],
+        data,
+    )
+        .into_response()
+}
 
-    let mut pages: Vec<&str> = zip.file_names().filter(|f| should_expose(f)).collect();
-    pages.sort();
+/// Show the page at `subpath` of a paged document, or its image data if
+/// `raw` was requested. Pages the browser can't show, going by the `Accept`
+/// header given with `transcode`, are returned to be converted.
+fn show_pages(
+    file: &File,
+    booklet: Booklet,
+    document: &mut dyn Document,
+    subpath: &str,
+    query: ShowFileQuery,
+    transcode: Option<(&Transcoder, &str)>,
+) -> Result<Shown, InternalError> {
+    let pages = document.pages(file.info.as_ref())?;
     if pages.is_empty() {
-        return Ok(StatusCode::NOT_FOUND.into_response());
+        return Ok(StatusCode::NOT_FOUND.into_response().into());
     }
 
-    let subpath = path.strip_prefix(&file.relative_path);
-    let page_index = if subpath.is_some_and(|s| s != "") {
-        let subpath = subpath.unwrap();
+    let page_index = if !subpath.is_empty() {
         if !(subpath.starts_with("/") && should_expose(subpath)) {
-            return Ok(StatusCode::NOT_FOUND.into_response());
+            return Ok(StatusCode::NOT_FOUND.into_response().into());
         }
 
         let subpath = subpath.strip_prefix("/").unwrap();
-        let page_index = pages.iter().position(|p| p == &subpath);
-        if !page_index.is_some() {
-            return Ok(StatusCode::NOT_FOUND.into_response());
+        let page_index = pages.iter().position(|p| p == subpath);
+        if page_index.is_none() {
+            return Ok(StatusCode::NOT_FOUND.into_response().into());
         }
         if query.raw.is_some() {
-            let mut page = zip.by_name(subpath)?;
-
-            let mut data = vec![];
-            page.read_to_end(&mut data)?;
-
-            let content_type = if subpath.ends_with(".gif") {
-                "image/gif"
-            } else {
-                "image/jpeg"
-            };
-
-            return Ok((
-                [
-                    (header::CONTENT_TYPE, content_type),
-                    (header::CACHE_CONTROL, "public, max-age=31536000"),
-                    (
-                        header::LAST_MODIFIED,
-                        &http_date_from_zip(page.last_modified())?,
-                    ),
-                ],
-                data,
-            )
-                .into_response());
+            let page = document.read_page(subpath)?;
+            let content_type = image_type::content_type(subpath, &page.data);
+            if let Some((transcoder, accept)) = transcode
+                && transcoder.converts(content_type, accept)
+            {
+                return Ok(Shown::Convert(Conversion {
+                    transcoder: transcoder.clone(),
+                    key: PageKey {
+                        path: file.path.clone(),
+                        version: file.version(),
+                        page: subpath.to_string(),
+                    },
+                    data: page.data,
+                    content_type,
+                    accept: accept.to_string(),
+                    modified: page.modified,
+                }));
+            }
+            return Ok(page_response(page.data, content_type, page.modified).into());
         }
         page_index.unwrap()
     } else {
@@ -649,42 +1669,45 @@ fn show_cbz(file: &File, path: String, query: ShowFileQuery) -> Result<Response,
     } else {
         None
     };
-    let current = pages[page_index];
+    let current = &pages[page_index];
     let next = pages.get(page_index + 1);
 
     let ctx = ViewTemplate {
         file,
-        image_url: format!(
-            "/view/{}/{}?raw&v={}",
-            encode_path_segment(file.relative_path.as_str()),
-            encode_path_segment(current),
-            file.version(),
-        ),
-        next_url: next.map(|next| {
-            format!(
+        image_url: file.raw_page_url(current),
+        page_index,
+        next_url: match next {
+            Some(next) => Some(format!(
                 "/view/{}/{}",
-                encode_path_segment(file.relative_path.as_str()),
+                file.encoded_path(),
                 encode_path_segment(next)
-            )
-        }),
-        previous_url: previous.map(|previous| {
-            format!(
+            )),
+            // continue into the next booklet
+            None => booklet.next.map(|b| b.view_url()),
+        },
+        previous_url: match previous {
+            Some(previous) => Some(format!(
                 "/view/{}/{}",
-                encode_path_segment(file.relative_path.as_str()),
+                file.encoded_path(),
                 encode_path_segment(previous)
-            )
-        }),
+            )),
+            None => booklet.previous.map(last_page_url),
+        },
+        booklet,
     };
-    Ok(Html(ctx.render_once()?).into_response())
+    Ok(Html(ctx.render_once()?).into_response().into())
 }
 
-async fn show_pdf(
+/// Show a document the browser displays itself, like a PDF
+async fn show_whole(
     file: &File,
-    path: String,
+    booklet: Booklet<'_>,
+    content_type: &str,
+    subpath: &str,
     query: ShowFileQuery,
 ) -> Result<Response, InternalError> {
-    // can't navigate to specific pages in the PDF
-    if path != file.relative_path {
+    // can't navigate to specific pages
+    if !subpath.is_empty() {
         return Ok(StatusCode::NOT_FOUND.into_response());
     }
     if query.raw.is_some() {
@@ -693,7 +1716,7 @@ async fn show_pdf(
 
         return Ok((
             [
-                (header::CONTENT_TYPE, "application/pdf"),
+                (header::CONTENT_TYPE, content_type),
                 (header::CACHE_CONTROL, "public, max-age=31536000"),
                 (header::LAST_MODIFIED, &fmt_http_date(file.modified)),
             ],
@@ -704,19 +1727,28 @@ async fn show_pdf(
 
     let ctx = PdfViewTemplate {
         file,
-        image_url: format!(
-            "/view/{}?raw&v={}",
-            encode_path_segment(file.relative_path.as_str()),
-            file.version(),
-        ),
+        booklet,
+        image_url: format!("/view/{}?raw&v={}", file.encoded_path(), file.version(),),
     };
     Ok(Html(ctx.render_once()?).into_response())
 }
 
-fn http_date_from_zip(date: Option<zip::DateTime>) -> Result<String> {
-    let date = date.context("missing last-modified")?;
-    let date = NaiveDateTime::try_from(date)?;
-    Ok(fmt_http_date(date.and_utc().into()))
+/// URL of the last page of `file`, or of the file itself if it isn't paged
+fn last_page_url(file: &File) -> String {
+    let last_page = document::open(&file.path).and_then(|mut document| {
+        if document.whole_content_type().is_some() {
+            return Ok(None);
+        }
+        Ok(document.pages(file.info.as_ref())?.pop())
+    });
+    match last_page {
+        Ok(Some(page)) => format!(
+            "/view/{}/{}",
+            file.encoded_path(),
+            encode_path_segment(&page)
+        ),
+        _ => file.view_url(),
+    }
 }
 
 fn encode_path_segment<'a>(str: &'a str) -> PercentEncode<'a> {
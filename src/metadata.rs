@@ -0,0 +1,179 @@
+//! Metadata kept outside the documents themselves: sidecar files next to a
+//! document, and a per-library overrides file.
+//!
+//! Fields are merged one by one, each taken from the first of these that
+//! has it:
+//!
+//! 1. the library's overrides file, by set number
+//! 2. a `<name>.ComicInfo.xml` sidecar
+//! 3. a `<name>.xmp` sidecar
+//! 4. a `ComicInfo.xml` next to the document, shared by every document in
+//!    its directory
+//! 5. metadata inside the document
+
+use crate::ComicInfo;
+use crate::document::{self, read_comic_info};
+use crate::set_number::SetNumber;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+use xmp_toolkit::XmpMeta;
+
+/// Per-library file of metadata overrides, in the library root
+pub const OVERRIDES_FILE_NAME: &str = "lview-overrides.toml";
+
+const COMIC_INFO_SUFFIX: &str = ".ComicInfo.xml";
+const XMP_SUFFIX: &str = ".xmp";
+const SHARED_COMIC_INFO: &str = "ComicInfo.xml";
+
+/// Sidecar paths for the document at `path`, in order of precedence
+fn sidecar_paths(path: &Path) -> [PathBuf; 3] {
+    let stem = if path.is_dir() {
+        path.file_name()
+    } else {
+        path.file_stem()
+    }
+    .unwrap_or_default();
+    let [comic_info, xmp] = [COMIC_INFO_SUFFIX, XMP_SUFFIX].map(|suffix| {
+        let mut name = stem.to_owned();
+        name.push(suffix);
+        path.with_file_name(name)
+    });
+    [comic_info, xmp, path.with_file_name(SHARED_COMIC_INFO)]
+}
+
+/// Metadata from the sidecars of the document at `path`
+pub fn read_sidecars(path: &Path) -> Result<Option<ComicInfo>> {
+    let [comic_info_path, xmp_path, shared_path] = sidecar_paths(path);
+    let comic_info = read_comic_info_sidecar(&comic_info_path)?;
+    let xmp = if xmp_path.exists() {
+        // parsed as a string, since reading the file only finds XMP wrapped
+        // in an xpacket
+        let data = fs::read_to_string(&xmp_path)?;
+        let xmp = XmpMeta::from_str(&data).context("invalid XMP sidecar")?;
+        Some(ComicInfo::from_xmp(&xmp))
+    } else {
+        None
+    };
+    let shared = read_comic_info_sidecar(&shared_path)?;
+    Ok([comic_info, xmp, shared]
+        .into_iter()
+        .flatten()
+        .reduce(ComicInfo::or))
+}
+
+fn read_comic_info_sidecar(path: &Path) -> Result<Option<ComicInfo>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let file = fs::File::open(path)?;
+    let info = read_comic_info(file)
+        .with_context(|| format!("invalid {}", path.file_name().unwrap().display()))?;
+    Ok(Some(info))
+}
+
+/// When the sidecars of the document at `path` were last modified, if it
+/// has any
+pub fn sidecars_modified(path: &Path) -> Option<SystemTime> {
+    sidecar_paths(path)
+        .iter()
+        .filter_map(|p| fs::metadata(p).and_then(|m| m.modified()).ok())
+        .max()
+}
+
+/// The document that `path` is a sidecar of, if it is one and the document
+/// exists. A shared `ComicInfo.xml` gives its directory, since it belongs to
+/// every document there.
+pub fn document_for_sidecar(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    if name == SHARED_COMIC_INFO {
+        return path.parent().map(Path::to_path_buf);
+    }
+    let stem = [COMIC_INFO_SUFFIX, XMP_SUFFIX]
+        .iter()
+        .find_map(|suffix| name.strip_suffix(suffix))?;
+    let dir = path.with_file_name(stem);
+    if document::is_image_dir(&dir) {
+        return Some(dir);
+    }
+    let parent = path.parent()?;
+    fs::read_dir(parent)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .find(|p| {
+            p.file_stem().is_some_and(|s| s == stem) && !p.is_dir() && document::is_supported(p)
+        })
+}
+
+/// Metadata overrides keyed by set number, in any form that names the same
+/// set, with fields named as in ComicInfo.xml:
+///
+/// ```toml
+/// ["10305"]
+/// Title = "Lion Knights' Castle"
+/// Genre = "Castle,Icons"
+/// Month = 8
+/// ```
+#[derive(Debug, Default)]
+pub struct Overrides {
+    sets: HashMap<SetNumber, ComicInfo>,
+    /// Why the overrides file was ignored, if it was
+    pub error: Option<String>,
+}
+
+impl Overrides {
+    /// Read the overrides file in `dir`, treating a missing or invalid file
+    /// as empty.
+    pub fn load(dir: &Path) -> Self {
+        let path = dir.join(OVERRIDES_FILE_NAME);
+        if !path.exists() {
+            return Self::default();
+        }
+        let sets = fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| Ok(toml::from_str::<HashMap<String, ComicInfo>>(&data)?));
+        match sets {
+            Ok(sets) => Self {
+                sets: sets
+                    .into_iter()
+                    .map(|(number, info)| (SetNumber::parse(&number), info))
+                    .collect(),
+                error: None,
+            },
+            Err(e) => {
+                eprintln!("ignoring overrides {}: {:#}", path.display(), e);
+                Self {
+                    error: Some(format!("{:#}", e)),
+                    ..Self::default()
+                }
+            }
+        }
+    }
+
+    /// Apply the overrides for `info`'s set number, or if it has none the
+    /// number at the start of the file name
+    pub fn apply(&self, info: Option<ComicInfo>, path: &Path) -> Option<ComicInfo> {
+        let number = match &info {
+            Some(info) if !info.number.is_empty() => info.number.clone(),
+            _ => leading_number(path),
+        };
+        match self.sets.get(&SetNumber::parse(&number)) {
+            Some(overrides) => {
+                let info = info.unwrap_or_else(|| ComicInfo {
+                    number,
+                    ..ComicInfo::default()
+                });
+                Some(overrides.clone().or(info))
+            }
+            None => info,
+        }
+    }
+}
+
+fn leading_number(path: &Path) -> String {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    name.chars().take_while(|c| c.is_ascii_digit()).collect()
+}
@@ -0,0 +1,123 @@
+//! Optional numbers in ComicInfo.xml, where an element or attribute may be
+//! present but empty.
+
+use serde::de::{Error, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+pub fn serialize<T: Display, S: Serializer>(
+    value: &Option<T>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    value.as_ref().map(|v| v.to_string()).serialize(serializer)
+}
+
+/// A number as text. Numbers are text in XML, but TOML overrides can also
+/// give them as numbers.
+struct Text(String);
+
+impl<'de> Deserialize<'de> for Text {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TextVisitor;
+
+        impl Visitor<'_> for TextVisitor {
+            type Value = Text;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a number")
+            }
+
+            fn visit_str<E: Error>(self, v: &str) -> Result<Text, E> {
+                Ok(Text(v.to_string()))
+            }
+
+            fn visit_i64<E: Error>(self, v: i64) -> Result<Text, E> {
+                Ok(Text(v.to_string()))
+            }
+
+            fn visit_u64<E: Error>(self, v: u64) -> Result<Text, E> {
+                Ok(Text(v.to_string()))
+            }
+
+            fn visit_f64<E: Error>(self, v: f64) -> Result<Text, E> {
+                Ok(Text(v.to_string()))
+            }
+        }
+
+        deserializer.deserialize_str(TextVisitor)
+    }
+}
+
+/// Values that don't parse, like `4,5` or `2021-06`, are logged and left
+/// out rather than failing the whole file.
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: FromStr<Err: Display>,
+    D: Deserializer<'de>,
+{
+    let Some(Text(s)) = Option::<Text>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let s = s.trim();
+    if s.is_empty() {
+        return Ok(None);
+    }
+    match s.parse() {
+        Ok(value) => Ok(Some(value)),
+        Err(e) => {
+            eprintln!("ignoring invalid value {:?}: {}", s, e);
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Default)]
+    #[serde(default, rename_all = "PascalCase")]
+    struct Info {
+        #[serde(with = "super")]
+        year: Option<i32>,
+        #[serde(with = "super")]
+        community_rating: Option<f32>,
+        #[serde(rename = "@Image", with = "super")]
+        image: Option<usize>,
+        #[serde(rename = "@ImageWidth", with = "super")]
+        image_width: Option<u32>,
+        #[serde(rename = "@DoublePage", with = "super")]
+        double_page: Option<bool>,
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let info: Info = quick_xml::de::from_str(
+            "<Info><Year> 2021 </Year><CommunityRating>4.5</CommunityRating></Info>",
+        )
+        .unwrap();
+        assert_eq!((info.year, info.community_rating), (Some(2021), Some(4.5)));
+
+        let info: Info = quick_xml::de::from_str(
+            "<Info><Year>2021-06</Year><CommunityRating>4,5</CommunityRating></Info>",
+        )
+        .unwrap();
+        assert_eq!((info.year, info.community_rating), (None, None));
+
+        let info: Info = quick_xml::de::from_str("<Info><Year/></Info>").unwrap();
+        assert_eq!(info.year, None);
+
+        let info: Info = quick_xml::de::from_str(
+            r#"<Info Image="0" ImageWidth="wide" DoublePage="true"><Year>2021</Year></Info>"#,
+        )
+        .unwrap();
+        assert_eq!(info.year, Some(2021));
+        assert_eq!(info.image, Some(0));
+        assert_eq!(info.image_width, None);
+        assert_eq!(info.double_page, Some(true));
+
+        let info: Info = toml::from_str("Year = 2021\nCommunityRating = 4.5").unwrap();
+        assert_eq!((info.year, info.community_rating), (Some(2021), Some(4.5)));
+    }
+}
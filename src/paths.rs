@@ -0,0 +1,50 @@
+//! Paths are carried as raw OS strings so that files with names that aren't
+//! valid UTF-8 can still be served.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Rebuild a path from the bytes produced by `OsStr::as_encoded_bytes`.
+pub fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        OsString::from_vec(bytes).into()
+    }
+    #[cfg(not(unix))]
+    {
+        OsString::from(String::from_utf8_lossy(&bytes).into_owned()).into()
+    }
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum RawPath<'a> {
+    Utf8(&'a str),
+    Bytes(&'a [u8]),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OwnedRawPath {
+    Utf8(String),
+    Bytes(Vec<u8>),
+}
+
+/// Serialize paths as strings, falling back to raw bytes when they aren't
+/// valid UTF-8, since serde's own `Path` support rejects those.
+pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    match path.to_str() {
+        Some(s) => RawPath::Utf8(s),
+        None => RawPath::Bytes(path.as_os_str().as_encoded_bytes()),
+    }
+    .serialize(serializer)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+    Ok(match OwnedRawPath::deserialize(deserializer)? {
+        OwnedRawPath::Utf8(s) => s.into(),
+        OwnedRawPath::Bytes(b) => path_from_bytes(b),
+    })
+}
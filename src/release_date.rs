@@ -0,0 +1,86 @@
+//! Release dates, known to the year and possibly the month and day.
+
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+/// A date as precise as the metadata allows. Ordering is chronological, with
+/// a less precise date before the more precise dates it contains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ReleaseDate {
+    pub year: i32,
+    pub month: Option<u32>,
+    pub day: Option<u32>,
+}
+
+impl ReleaseDate {
+    /// A date from separate fields, dropping a month or day that is out of
+    /// range, or a day without a month
+    pub fn new(year: i32, month: Option<u32>, day: Option<u32>) -> Self {
+        let month = month.filter(|m| (1..=12).contains(m));
+        let day = day.filter(|d| month.is_some() && (1..=31).contains(d));
+        Self { year, month, day }
+    }
+}
+
+/// Parse `YYYY`, `YYYY-MM` or `YYYY-MM-DD`, ignoring any time that follows
+/// as in XMP dates like `2021-06-01T00:00:00Z`.
+impl FromStr for ReleaseDate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let date = s.trim().split(['T', ' ']).next().unwrap_or_default();
+        let mut parts = date.split('-');
+        let year = parts
+            .next()
+            .unwrap_or_default()
+            .parse()
+            .with_context(|| format!("invalid year in date '{}'", s))?;
+        let mut next = || -> Result<Option<u32>> {
+            parts
+                .next()
+                .map(|p| p.parse().map_err(|_| anyhow!("invalid date '{}'", s)))
+                .transpose()
+        };
+        let (month, day) = (next()?, next()?);
+        let date = Self::new(year, month, day);
+        if (date.month, date.day) != (month, day) {
+            return Err(anyhow!("invalid date '{}'", s));
+        }
+        Ok(date)
+    }
+}
+
+impl Display for ReleaseDate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.year)?;
+        if let Some(month) = self.month {
+            write!(f, "-{:02}", month)?;
+        }
+        if let Some(day) = self.day {
+            write!(f, "-{:02}", day)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_release_date() {
+        let date = |s: &str| s.parse::<ReleaseDate>().ok();
+        assert_eq!(date("2021"), Some(ReleaseDate::new(2021, None, None)));
+        assert_eq!(
+            date("2021-06-01T00:00:00Z"),
+            Some(ReleaseDate::new(2021, Some(6), Some(1)))
+        );
+        assert_eq!(date("2021-13"), None);
+        assert_eq!(date("June 2021"), None);
+        assert!(date("2021") < date("2021-01"));
+        assert!(date("2021-01-31") < date("2021-02"));
+        assert!(date("2020-12-31") < date("2021"));
+    }
+}
@@ -0,0 +1,446 @@
+use crate::ComicInfo;
+use crate::cache::IndexCache;
+use crate::catalog::{Catalog, CatalogSet};
+use crate::document::{is_image_dir, is_supported};
+use crate::metadata::{OVERRIDES_FILE_NAME, Overrides};
+use crate::set_number::SetNumber;
+use crate::{File, SharedState};
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use std::{fs, io};
+use tokio::sync::watch;
+
+/// Progress of the initial library scan, readable without locking the state.
+#[derive(Debug)]
+pub struct ScanProgress {
+    total: AtomicUsize,
+    scanned: AtomicUsize,
+    finished: watch::Sender<bool>,
+}
+
+impl Default for ScanProgress {
+    fn default() -> Self {
+        Self {
+            total: AtomicUsize::new(0),
+            scanned: AtomicUsize::new(0),
+            finished: watch::Sender::new(false),
+        }
+    }
+}
+
+impl ScanProgress {
+    pub fn is_finished(&self) -> bool {
+        *self.finished.borrow()
+    }
+
+    /// Number of files scanned so far, and the total found
+    pub fn counts(&self) -> (usize, usize) {
+        (
+            self.scanned.load(Ordering::Relaxed),
+            self.total.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Receiver that flips to `true` once the scan is complete
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.finished.subscribe()
+    }
+
+    fn advance(&self) {
+        self.scanned.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn finish(&self) {
+        self.finished.send_replace(true);
+    }
+}
+
+/// A file that was found but couldn't be loaded
+#[derive(Debug)]
+pub struct Problem {
+    pub relative_path: String,
+    pub path: PathBuf,
+    pub error: String,
+}
+
+impl Problem {
+    fn new(path: PathBuf, library: &Library, error: &anyhow::Error) -> Self {
+        eprintln!("skipping {}: {:#}", path.display(), error);
+        Self::with_message(path, library, format!("{:#}", error))
+    }
+
+    fn with_message(path: PathBuf, library: &Library, error: String) -> Self {
+        let relative_path = path.strip_prefix(&library.dir).unwrap_or(&path);
+        let relative_path = if library.label.is_empty() {
+            relative_path.to_string_lossy().into_owned()
+        } else {
+            format!("{}/{}", library.label, relative_path.to_string_lossy())
+        };
+        Self {
+            relative_path,
+            path,
+            error,
+        }
+    }
+}
+
+/// Directories and files that are never part of the library: hidden files,
+/// NAS and OS housekeeping folders, and partial downloads.
+const DEFAULT_IGNORES: &[&str] = &[
+    ".*",
+    "@eaDir/",
+    "#recycle/",
+    "#snapshot/",
+    "$RECYCLE.BIN/",
+    "System Volume Information/",
+    "lost+found/",
+    "*.part",
+    "*.crdownload",
+];
+
+/// Per-directory ignore file, in gitignore syntax
+pub const IGNORE_FILE_NAME: &str = ".lviewignore";
+
+/// Identifies a file regardless of the path it was reached by
+type FileId = (u64, u64);
+
+fn file_id(metadata: &fs::Metadata) -> Option<FileId> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some((metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// A supported file found while walking the library
+struct Found {
+    path: PathBuf,
+    id: Option<FileId>,
+    /// Whether the path goes through a symlink
+    linked: bool,
+}
+
+/// State carried through a walk of the library
+#[derive(Default)]
+struct Walk {
+    ignores: Vec<Gitignore>,
+    /// Directories currently being walked, to detect symlink loops
+    ancestors: Vec<FileId>,
+    found: Vec<Found>,
+}
+
+/// A library root and the rules for which files below it are served.
+#[derive(Debug)]
+pub struct Library {
+    pub label: String,
+    pub dir: PathBuf,
+    ignore: Gitignore,
+    follow_symlinks: bool,
+    overrides: RwLock<Overrides>,
+    catalog: Arc<Catalog>,
+}
+
+impl Library {
+    pub fn new(
+        label: String,
+        dir: PathBuf,
+        ignore_globs: &[String],
+        default_ignores: bool,
+        follow_symlinks: bool,
+    ) -> Result<Self> {
+        let mut builder = GitignoreBuilder::new(&dir);
+        if default_ignores {
+            for glob in DEFAULT_IGNORES {
+                builder.add_line(None, glob)?;
+            }
+        }
+        for glob in ignore_globs {
+            builder
+                .add_line(None, glob)
+                .with_context(|| format!("invalid ignore pattern '{}'", glob))?;
+        }
+        let ignore = builder.build()?;
+        let overrides = RwLock::new(Overrides::load(&dir));
+        Ok(Self {
+            label,
+            dir,
+            ignore,
+            follow_symlinks,
+            overrides,
+            catalog: Arc::default(),
+        })
+    }
+
+    pub fn with_catalog(self, catalog: Arc<Catalog>) -> Self {
+        Self { catalog, ..self }
+    }
+
+    pub fn catalog_set(&self, number: &SetNumber) -> Option<Arc<CatalogSet>> {
+        self.catalog.get(number)
+    }
+
+    pub fn overrides_path(&self) -> PathBuf {
+        self.dir.join(OVERRIDES_FILE_NAME)
+    }
+
+    /// When the overrides file was last modified, if there is one
+    pub fn overrides_modified(&self) -> Option<SystemTime> {
+        fs::metadata(self.overrides_path())
+            .and_then(|m| m.modified())
+            .ok()
+    }
+
+    pub fn reload_overrides(&self) {
+        *self.overrides.write().unwrap() = Overrides::load(&self.dir);
+    }
+
+    /// The overrides file, if it was ignored because it couldn't be read
+    pub fn overrides_problem(&self) -> Option<Problem> {
+        let error = self.overrides.read().unwrap().error.clone()?;
+        Some(Problem::with_message(self.overrides_path(), self, error))
+    }
+
+    pub fn apply_overrides(&self, info: Option<ComicInfo>, path: &Path) -> Option<ComicInfo> {
+        self.overrides.read().unwrap().apply(info, path)
+    }
+
+    /// Whether `path` is a supported file that isn't excluded by any ignore
+    /// rule, including `.lviewignore` files in its ancestors.
+    pub fn is_included(&self, path: &Path) -> bool {
+        if !is_supported(path) {
+            return false;
+        }
+        let Some(parent) = path.parent().filter(|p| p.starts_with(&self.dir)) else {
+            return false;
+        };
+        if !self.follow_symlinks {
+            let mut paths = path.ancestors().take_while(|p| *p != self.dir);
+            if paths.any(|p| p.is_symlink()) {
+                return false;
+            }
+        }
+        let ignores = self.ancestor_ignores(parent);
+        // check each directory on the way down, as the walk would
+        let mut dirs = parent.ancestors().take_while(|d| *d != self.dir);
+        if dirs.any(|d| self.is_ignored(&ignores, d, true)) {
+            return false;
+        }
+        !self.is_ignored(&ignores, path, false)
+    }
+
+    /// Find supported files under `dir`, which must be inside the library.
+    ///
+    /// Symlinks back to an ancestor directory are skipped rather than looping,
+    /// and a file reachable by several paths is only returned once, preferring
+    /// a path without symlinks.
+    pub fn find_files(&self, dir: &Path) -> Result<Vec<PathBuf>, io::Error> {
+        if dir != self.dir && is_image_dir(dir) {
+            return Ok(vec![dir.to_path_buf()]);
+        }
+        let mut walk = Walk {
+            ignores: self.ancestor_ignores(dir),
+            ..Walk::default()
+        };
+        walk.ancestors.extend(file_id(&fs::metadata(dir)?));
+        self.collect_files(dir, false, &mut walk)?;
+
+        let mut found = walk.found;
+        found.sort_by(|a, b| a.linked.cmp(&b.linked).then_with(|| a.path.cmp(&b.path)));
+        let mut seen = HashSet::new();
+        Ok(found
+            .into_iter()
+            .filter(|f| f.id.is_none_or(|id| seen.insert(id)))
+            .map(|f| f.path)
+            .collect())
+    }
+
+    /// `.lviewignore` files from the library root down to `dir`, outermost first
+    fn ancestor_ignores(&self, dir: &Path) -> Vec<Gitignore> {
+        let mut dirs = dir
+            .ancestors()
+            .take_while(|d| d.starts_with(&self.dir))
+            .collect::<Vec<_>>();
+        dirs.reverse();
+        dirs.into_iter().filter_map(read_ignore_file).collect()
+    }
+
+    fn collect_files(&self, parent: &Path, linked: bool, walk: &mut Walk) -> Result<(), io::Error> {
+        for entry in fs::read_dir(parent)? {
+            let entry = entry?;
+            let path = entry.path();
+            let is_symlink = entry.file_type()?.is_symlink();
+            if is_symlink && !self.follow_symlinks {
+                continue;
+            }
+            let metadata = match fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    eprintln!("skipping {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let linked = linked || is_symlink;
+
+            if metadata.is_dir() {
+                if self.is_ignored(&walk.ignores, &path, true) {
+                    continue;
+                }
+                let id = file_id(&metadata);
+                if is_image_dir(&path) {
+                    walk.found.push(Found { path, id, linked });
+                    continue;
+                }
+                if id.is_some_and(|id| walk.ancestors.contains(&id)) {
+                    eprintln!("skipping {}: symlink loop", path.display());
+                    continue;
+                }
+                walk.ancestors.extend(id);
+                let pushed = read_ignore_file(&path).map(|i| walk.ignores.push(i));
+                let result = self.collect_files(&path, linked, walk);
+                if pushed.is_some() {
+                    walk.ignores.pop();
+                }
+                if id.is_some() {
+                    walk.ancestors.pop();
+                }
+                result?
+            } else if is_supported(&path) && !self.is_ignored(&walk.ignores, &path, false) {
+                walk.found.push(Found {
+                    path,
+                    id: file_id(&metadata),
+                    linked,
+                })
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the innermost `.lviewignore` first, so deeper files can
+    /// re-include what a parent or the global rules exclude.
+    fn is_ignored(&self, ignores: &[Gitignore], path: &Path, is_dir: bool) -> bool {
+        ignores
+            .iter()
+            .rev()
+            .chain([&self.ignore])
+            .filter(|i| path.starts_with(i.path()) && path != i.path())
+            .map(|i| i.matched(path, is_dir))
+            .find(|m| !m.is_none())
+            .is_some_and(|m| m.is_ignore())
+    }
+}
+
+fn read_ignore_file(dir: &Path) -> Option<Gitignore> {
+    let path = dir.join(IGNORE_FILE_NAME);
+    if !path.is_file() {
+        return None;
+    }
+    let (ignore, error) = Gitignore::new(&path);
+    if let Some(e) = error {
+        eprintln!("{}: {}", path.display(), e);
+    }
+    Some(ignore)
+}
+
+/// Load `paths` in parallel, reusing cached entries where possible and
+/// collecting the files that fail to load instead of giving up.
+pub fn load_files(
+    paths: Vec<PathBuf>,
+    library: &Library,
+    mut cache: Option<&mut IndexCache>,
+    progress: Option<&ScanProgress>,
+) -> (Vec<File>, Vec<Problem>) {
+    let mut files = vec![];
+    let mut uncached = vec![];
+    for path in paths {
+        match cache.as_mut().and_then(|c| c.take(&path, library)) {
+            Some(file) => {
+                files.push(file);
+                progress.inspect(|p| p.advance());
+            }
+            None => uncached.push(path),
+        }
+    }
+
+    let results = uncached
+        .into_par_iter()
+        .map(|path| {
+            let result =
+                File::from_path(path.clone(), library).map_err(|e| Problem::new(path, library, &e));
+            progress.inspect(|p| p.advance());
+            result
+        })
+        .collect::<Vec<_>>();
+
+    let mut problems = vec![];
+    for result in results {
+        match result {
+            Ok(file) => files.push(file),
+            Err(problem) => problems.push(problem),
+        }
+    }
+    (files, problems)
+}
+
+/// Scan every library and fill in `state`, while the server is already
+/// answering requests.
+pub async fn scan_library(libraries: Vec<Arc<Library>>, state: SharedState) {
+    let (progress, cache_path) = {
+        let state = state.read().await;
+        (state.scan.clone(), state.cache_path.clone())
+    };
+
+    let scan_progress = progress.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let mut cache = cache_path
+            .as_deref()
+            .map_or_else(IndexCache::default, IndexCache::load);
+        let mut found = vec![];
+        for library in &libraries {
+            let entries = library.find_files(&library.dir)?;
+            println!("found {} files in {}", entries.len(), library.dir.display());
+            scan_progress
+                .total
+                .fetch_add(entries.len(), Ordering::Relaxed);
+            found.push((library, entries));
+        }
+
+        let mut files = vec![];
+        let mut problems = vec![];
+        for (library, entries) in found {
+            let (f, p) = load_files(entries, library, Some(&mut cache), Some(&scan_progress));
+            files.extend(f);
+            problems.extend(p);
+            problems.extend(library.overrides_problem());
+        }
+        Ok::<_, io::Error>((files, problems))
+    })
+    .await;
+
+    match result {
+        Ok(Ok((files, problems))) => {
+            if !problems.is_empty() {
+                println!("skipped {} files, see /admin", problems.len());
+            }
+            let mut state = state.write().await;
+            state.files = files;
+            state.problems = problems;
+            state.update_indexes();
+            state.save_cache();
+        }
+        Ok(Err(e)) => eprintln!("failed to scan library: {}", e),
+        Err(e) => eprintln!("failed to scan library: {}", e),
+    }
+    println!("scan complete");
+    progress.finish();
+}
@@ -0,0 +1,106 @@
+//! LEGO set numbers, like `10294-1`, `6000-2`, `K-8` or `TRU04`.
+
+use std::fmt::{self, Display, Formatter};
+
+/// A parsed set number. Sets order by their number, then prefix, suffix and
+/// variant; numbers that don't fit the pattern follow in alphabetical order.
+///
+/// Set numbers are equal if they name the same set, so `10294` equals
+/// `10294-1` and `k-8` equals `K8`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SetNumber {
+    Set {
+        base: u32,
+        /// Letters before the number, in upper case, e.g. `TRU` or `K`
+        prefix: String,
+        /// Letters after the number, in lower case, e.g. the `a` of `6000a`
+        suffix: String,
+        /// Variant after the dash, 1 if not given
+        variant: u32,
+    },
+    /// Anything else, in lower case
+    Other(String),
+}
+
+impl SetNumber {
+    /// Parse `[prefix][-]base[suffix][-variant]`, keeping anything else as
+    /// it is
+    pub fn parse(number: &str) -> Self {
+        let number = number.trim();
+        Self::parse_set(number).unwrap_or_else(|| Self::Other(number.to_lowercase()))
+    }
+
+    fn parse_set(number: &str) -> Option<Self> {
+        let (prefix, rest) = split_leading(number, |c| c.is_ascii_alphabetic());
+        let rest = if prefix.is_empty() {
+            rest
+        } else {
+            rest.strip_prefix('-').unwrap_or(rest)
+        };
+        let (base, rest) = split_leading(rest, |c| c.is_ascii_digit());
+        let (suffix, rest) = split_leading(rest, |c| c.is_ascii_alphabetic());
+        let variant = match rest {
+            "" => 1,
+            rest => {
+                let variant = rest.strip_prefix('-')?;
+                if variant.is_empty() || !variant.chars().all(|c| c.is_ascii_digit()) {
+                    return None;
+                }
+                variant.parse().ok()?
+            }
+        };
+        Some(Self::Set {
+            base: base.parse().ok()?,
+            prefix: prefix.to_ascii_uppercase(),
+            suffix: suffix.to_ascii_lowercase(),
+            variant,
+        })
+    }
+
+    /// The set number as it appears in URLs, e.g. `k8-1`
+    pub fn slug(&self) -> String {
+        self.to_string().to_lowercase()
+    }
+}
+
+/// Canonical form, e.g. `TRU6000a-2`
+impl Display for SetNumber {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Set {
+                base,
+                prefix,
+                suffix,
+                variant,
+            } => write!(f, "{}{}{}-{}", prefix, base, suffix, variant),
+            Self::Other(number) => write!(f, "{}", number),
+        }
+    }
+}
+
+fn split_leading(s: &str, matches: impl Fn(char) -> bool) -> (&str, &str) {
+    s.split_at(s.find(|c| !matches(c)).unwrap_or(s.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_number() {
+        assert_eq!(SetNumber::parse("10294"), SetNumber::parse("10294-1"));
+        assert_eq!(SetNumber::parse("K-8"), SetNumber::parse("k8"));
+        assert_eq!(SetNumber::parse("TRU04").slug(), "tru4-1");
+        assert_eq!(SetNumber::parse("Hello 123").slug(), "hello 123");
+        let mut numbers = vec![
+            "10294-1", "Hello", "6000-2", "K-8", "6000", "6000a", "TRU04",
+        ];
+        numbers.sort_by_key(|n| SetNumber::parse(n));
+        assert_eq!(
+            numbers,
+            [
+                "TRU04", "K-8", "6000", "6000-2", "6000a", "10294-1", "Hello"
+            ]
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use anyhow::Result;
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageFormat};
+use jxl_oxide::integration::JxlDecoder;
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+
+/// Page formats every browser can show, which are never converted
+const UNIVERSAL: &[&str] = &["image/jpeg", "image/png", "image/gif"];
+
+const JPEG_QUALITY: u8 = 90;
+
+/// Size the converted pages on disk may grow to before the oldest are
+/// removed at startup
+const CACHE_LIMIT: u64 = 1 << 30;
+
+/// Identifies a page by its file, the file's version and the page's name
+/// within it, for naming converted pages on disk
+#[derive(Debug)]
+pub struct PageKey {
+    pub path: PathBuf,
+    pub version: String,
+    pub page: String,
+}
+
+impl PageKey {
+    /// FNV-1a of the key, which unlike `DefaultHasher` stays the same across
+    /// Rust releases, so converted pages are found again after an upgrade
+    fn stable_hash(&self, prefer_png: Option<bool>) -> u64 {
+        let prefer_png = [prefer_png.map_or(2, u8::from)];
+        let parts = [
+            self.path.as_os_str().as_encoded_bytes(),
+            self.version.as_bytes(),
+            self.page.as_bytes(),
+            &prefer_png,
+        ];
+        let mut hash = 0xcbf29ce484222325u64;
+        // 0xff separates the parts, since it never occurs in UTF-8
+        for byte in parts.iter().flat_map(|p| p.iter().chain(&[0xff])) {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
+}
+
+/// Converts pages to JPEG or PNG for browsers that can't show their format,
+/// keeping the results on disk.
+#[derive(Debug, Clone)]
+pub struct Transcoder {
+    cache_dir: Option<PathBuf>,
+}
+
+impl Transcoder {
+    pub fn new(cache_dir: Option<PathBuf>) -> Self {
+        Self { cache_dir }
+    }
+
+    /// Default location for converted pages under the user's cache directory
+    pub fn default_cache_dir() -> Option<PathBuf> {
+        dirs::cache_dir().map(|d| d.join("lview").join("pages"))
+    }
+
+    /// Remove the oldest converted pages until the rest fit in
+    /// [`CACHE_LIMIT`]. Pages of files that have since changed are never
+    /// read again, so they go first in time.
+    pub fn prune(&self) -> io::Result<()> {
+        let Some(dir) = &self.cache_dir else {
+            return Ok(());
+        };
+        let entries = match fs::read_dir(dir) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            entries => entries?,
+        };
+        let mut pages = vec![];
+        for entry in entries {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                pages.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+        pages.sort();
+        let mut size: u64 = pages.iter().map(|(_, len, _)| len).sum();
+        let mut removed = 0;
+        for (_, len, path) in pages {
+            if size <= CACHE_LIMIT {
+                break;
+            }
+            fs::remove_file(path)?;
+            size -= len;
+            removed += 1;
+        }
+        if removed > 0 {
+            println!("removed {} old pages from {}", removed, dir.display());
+        }
+        Ok(())
+    }
+
+    /// Whether a page of `content_type` has to be converted for a browser
+    /// sending the `Accept` header `accept`
+    pub fn converts(&self, content_type: &str, accept: &str) -> bool {
+        !UNIVERSAL.contains(&content_type) && !accepts(accept, content_type)
+    }
+
+    /// Convert a page of `content_type` unless the `Accept` header says the
+    /// browser can show it. This can be slow, so run it on a blocking thread.
+    ///
+    /// Returns the converted data and its content type, or `None` if the
+    /// page can be served as it is.
+    pub fn transcode(
+        &self,
+        key: &PageKey,
+        data: &[u8],
+        content_type: &str,
+        accept: &str,
+    ) -> Result<Option<(Vec<u8>, &'static str)>> {
+        if !self.converts(content_type, accept) {
+            return Ok(None);
+        }
+        let prefer_png = match (accepts(accept, "image/png"), accepts(accept, "image/jpeg")) {
+            (true, false) => Some(true),
+            (false, true) => Some(false),
+            _ => None,
+        };
+
+        let name = format!("{:016x}", key.stable_hash(prefer_png));
+        if let Some(cached) = self
+            .cache_dir
+            .as_deref()
+            .and_then(|d| read_cached(d, &name))
+        {
+            return Ok(Some(cached));
+        }
+
+        let image = decode(data, content_type)?;
+        let (data, format) = encode(&image, prefer_png.unwrap_or(image.color().has_alpha()))?;
+        if let Some(dir) = &self.cache_dir
+            && let Err(e) = write_cached(dir, &name, format, &data)
+        {
+            eprintln!("failed to cache page in {}: {}", dir.display(), e);
+        }
+        Ok(Some((data, format.to_mime_type())))
+    }
+}
+
+/// Whether `accept` lists `content_type` explicitly. Wildcards don't count,
+/// since browsers send `image/*` whether or not they can show a format.
+fn accepts(accept: &str, content_type: &str) -> bool {
+    accept.split(',').any(|range| {
+        let mut params = range.split(';');
+        let media_type = params.next().unwrap_or_default().trim();
+        let quality = params
+            .find_map(|p| p.trim().strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        media_type.eq_ignore_ascii_case(content_type) && quality > 0.0
+    })
+}
+
+fn decode(data: &[u8], content_type: &str) -> Result<DynamicImage> {
+    if content_type == "image/jxl" {
+        let decoder = JxlDecoder::new(Cursor::new(data))?;
+        return Ok(DynamicImage::from_decoder(decoder)?);
+    }
+    Ok(image::load_from_memory(data)?)
+}
+
+fn encode(image: &DynamicImage, png: bool) -> Result<(Vec<u8>, ImageFormat)> {
+    let mut data = vec![];
+    if png {
+        image.write_to(&mut Cursor::new(&mut data), ImageFormat::Png)?;
+        Ok((data, ImageFormat::Png))
+    } else {
+        let encoder = JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY);
+        image.to_rgb8().write_with_encoder(encoder)?;
+        Ok((data, ImageFormat::Jpeg))
+    }
+}
+
+fn read_cached(dir: &Path, name: &str) -> Option<(Vec<u8>, &'static str)> {
+    [ImageFormat::Jpeg, ImageFormat::Png]
+        .into_iter()
+        .find_map(|format| {
+            let path = dir.join(name).with_extension(format.extensions_str()[0]);
+            Some((fs::read(path).ok()?, format.to_mime_type()))
+        })
+}
+
+fn write_cached(dir: &Path, name: &str, format: ImageFormat, data: &[u8]) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(name).with_extension(format.extensions_str()[0]);
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, data)?;
+    fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accepts() {
+        let chrome = "image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8";
+        assert!(accepts(chrome, "image/webp"));
+        assert!(!accepts(chrome, "image/jxl"));
+        assert!(!accepts("*/*", "image/tiff"));
+        assert!(accepts("image/jxl;q=0.5, image/*", "image/jxl"));
+        assert!(accepts("IMAGE/JXL", "image/jxl"));
+        assert!(!accepts("image/jxl;q=0", "image/jxl"));
+        assert!(!accepts("", "image/jxl"));
+    }
+}
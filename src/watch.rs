@@ -0,0 +1,145 @@
+use crate::SharedState;
+use crate::document::is_image_dir;
+use crate::metadata::document_for_sidecar;
+use crate::scan::{IGNORE_FILE_NAME, Library, load_files};
+use anyhow::Result;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+/// How long the library has to be quiet before changes are applied, so that
+/// files still being copied in aren't parsed half-written.
+const SETTLE_DELAY: Duration = Duration::from_secs(2);
+
+/// Watch `libraries` for changes and keep `state` in sync with what's on disk.
+///
+/// The returned watcher must be kept alive for as long as changes should be
+/// picked up.
+pub fn watch_files(libraries: Vec<Arc<Library>>, state: SharedState) -> Result<RecommendedWatcher> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let _ = tx.send(event);
+    })?;
+    for library in &libraries {
+        watcher.watch(&library.dir, RecursiveMode::Recursive)?;
+    }
+
+    tokio::spawn(async move {
+        // changes made during the initial scan are queued until it's complete
+        let mut scanned = state.read().await.scan.subscribe();
+        let _ = scanned.wait_for(|finished| *finished).await;
+
+        while let Some(event) = rx.recv().await {
+            let mut changed = BTreeSet::new();
+            collect_paths(event, &mut changed);
+            while let Ok(Some(event)) = timeout(SETTLE_DELAY, rx.recv()).await {
+                collect_paths(event, &mut changed);
+            }
+
+            let libraries = libraries.clone();
+            let state = state.clone();
+            let result =
+                tokio::task::spawn_blocking(move || apply_changes(&libraries, &state, changed));
+            if let Err(e) = result.await {
+                eprintln!("failed to apply changes: {}", e);
+            }
+        }
+    });
+
+    Ok(watcher)
+}
+
+fn collect_paths(event: notify::Result<Event>, changed: &mut BTreeSet<PathBuf>) {
+    match event {
+        Ok(event) if !event.kind.is_access() => changed.extend(event.paths),
+        Ok(_) => {}
+        Err(e) => eprintln!("watch error: {}", e),
+    }
+}
+
+fn apply_changes(libraries: &[Arc<Library>], state: &SharedState, changed: BTreeSet<PathBuf>) {
+    let mut updated: Vec<(&Library, Vec<PathBuf>)> = vec![];
+    let mut removed = vec![];
+    let mut reloaded = vec![];
+    for mut path in changed {
+        if let Some(document) = document_for_sidecar(&path) {
+            path = document;
+        }
+        // a page was added to or removed from a folder of images
+        if let Some(parent) = path.parent()
+            && !path.is_dir()
+            && is_image_dir(parent)
+        {
+            path = parent.to_path_buf();
+        }
+        // the innermost root, in case one library is nested inside another
+        let Some(library) = libraries
+            .iter()
+            .filter(|l| path.starts_with(&l.dir))
+            .max_by_key(|l| l.dir.components().count())
+        else {
+            continue;
+        };
+        if path.file_name().is_some_and(|n| n == IGNORE_FILE_NAME) {
+            // ignore rules changed, so reload the whole directory
+            path.pop();
+            removed.push(path.clone());
+        } else if path == library.overrides_path() {
+            // overrides changed, so reload the whole library
+            library.reload_overrides();
+            reloaded.push(library);
+            path.pop();
+        }
+        let mut entries = if path.is_dir() {
+            match library.find_files(&path) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    eprintln!("failed to scan {}: {}", path.display(), e);
+                    continue;
+                }
+            }
+        } else if path.exists() {
+            vec![path]
+        } else {
+            removed.push(path);
+            continue;
+        };
+        entries.retain(|path| library.is_included(path));
+        updated.push((library, entries));
+    }
+
+    let mut files = vec![];
+    let mut problems = vec![];
+    for (library, entries) in updated {
+        let (f, p) = load_files(entries, library, None, None);
+        files.extend(f);
+        problems.extend(p);
+    }
+
+    let mut state = state.blocking_write();
+    let mut count = 0;
+    for path in removed {
+        count += state.remove(&path);
+    }
+    for file in files {
+        println!("updated {}", file.display_path());
+        state.upsert(file);
+    }
+    for problem in problems {
+        state.quarantine(problem);
+    }
+    for library in reloaded {
+        let path = library.overrides_path();
+        state.problems.retain(|p| p.path != path);
+        state.problems.extend(library.overrides_problem());
+    }
+    if count > 0 {
+        println!("removed {} files", count);
+    }
+    state.update_indexes();
+    state.save_cache();
+}